@@ -0,0 +1,105 @@
+use crate::core::player::CURRENT_FOLDER;
+use crate::engine::effects::MasterParams;
+use crate::engine::impulse;
+use crate::error::AudioError;
+use crate::setup::audio::AudioHandle;
+use crate::setup::config::InstrumentConfig;
+use crate::state;
+use lazy_static::lazy_static;
+use std::sync::Mutex;
+use tauri::State;
+
+lazy_static! {
+    static ref CURRENT_EFFECTS: Mutex<MasterParams> = Mutex::new(MasterParams::default());
+}
+
+pub fn current() -> MasterParams {
+    CURRENT_EFFECTS.lock().unwrap().clone()
+}
+
+pub fn apply(handle: &AudioHandle, params: &MasterParams) {
+    handle.apply_effects(params);
+    *CURRENT_EFFECTS.lock().unwrap() = params.clone();
+}
+
+// The user's saved chain for this instrument wins over the one it ships with.
+pub fn for_instrument(folder: &str, config: &InstrumentConfig) -> MasterParams {
+    state::read()
+        .ok()
+        .and_then(|mut s| s.effects.remove(folder))
+        .unwrap_or_else(|| config.master_effects())
+}
+
+// Fails early on an impulse response that cannot be loaded, rather than
+// leaving the chain to drop it silently.
+fn check_impulse(params: &MasterParams) -> Result<(), String> {
+    match params.convolution_ir.as_deref() {
+        Some(name) => impulse::load(name).map(|_| ()).map_err(|e| e.to_string()),
+        None => Ok(()),
+    }
+}
+
+fn remember_for_instrument(params: MasterParams) -> Result<(), String> {
+    match CURRENT_FOLDER.lock().unwrap().as_deref() {
+        Some(folder) => {
+            state::set_instrument_effects(folder, params).map_err(|e: AudioError| e.to_string())
+        }
+        None => Ok(()),
+    }
+}
+
+#[tauri::command]
+pub async fn get_master_effects() -> Result<MasterParams, String> {
+    Ok(current())
+}
+
+#[tauri::command]
+pub async fn set_master_effects(
+    params: MasterParams,
+    handle: State<'_, AudioHandle>,
+) -> Result<(), String> {
+    check_impulse(&params)?;
+    apply(&handle, &params);
+    remember_for_instrument(params)
+}
+
+#[tauri::command]
+pub async fn list_effect_presets() -> Result<Vec<String>, String> {
+    let state = state::read().map_err(|e: AudioError| e.to_string())?;
+    let mut names: Vec<String> = state.effect_presets.into_keys().collect();
+    names.sort();
+    Ok(names)
+}
+
+#[tauri::command]
+pub async fn save_effect_preset(name: String) -> Result<(), String> {
+    state::save_effect_preset(&name, current()).map_err(|e: AudioError| e.to_string())
+}
+
+#[tauri::command]
+pub async fn load_effect_preset(
+    name: String,
+    handle: State<'_, AudioHandle>,
+) -> Result<MasterParams, String> {
+    let state = state::read().map_err(|e: AudioError| e.to_string())?;
+    let params = state
+        .effect_presets
+        .get(&name)
+        .cloned()
+        .ok_or_else(|| format!("Effect preset '{}' does not exist", name))?;
+
+    check_impulse(&params)?;
+    apply(&handle, &params);
+    remember_for_instrument(params.clone())?;
+    Ok(params)
+}
+
+#[tauri::command]
+pub async fn delete_effect_preset(name: String) -> Result<(), String> {
+    state::delete_effect_preset(&name).map_err(|e: AudioError| e.to_string())
+}
+
+#[tauri::command]
+pub async fn list_impulse_responses() -> Result<Vec<String>, String> {
+    impulse::list().map_err(|e| e.to_string())
+}
@@ -1,3 +1,6 @@
+pub mod effects;
 pub mod manager;
+pub mod output;
 pub mod player;
+pub mod renderer;
 pub mod visualizer;
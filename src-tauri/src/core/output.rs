@@ -0,0 +1,58 @@
+use crate::core::effects;
+use crate::engine::backend::device::{self, DeviceConfig, DeviceInfo};
+use crate::engine::backend::BackendKind;
+use crate::error::AudioError;
+use crate::setup::audio::AudioHandle;
+use crate::state;
+use serde::Serialize;
+use tauri::State;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OutputInfo {
+    pub backend: String,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<usize>,
+    pub config: DeviceConfig,
+}
+
+#[tauri::command]
+pub async fn list_audio_hosts() -> Result<Vec<String>, String> {
+    Ok(device::list_hosts())
+}
+
+#[tauri::command]
+pub async fn list_output_devices(host: Option<String>) -> Result<Vec<DeviceInfo>, String> {
+    device::list_devices(host.as_deref()).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn get_output_device(handle: State<'_, AudioHandle>) -> Result<OutputInfo, String> {
+    let config = state::read()
+        .map(|s| s.output_device)
+        .map_err(|e: AudioError| e.to_string())?;
+    let format = handle.format();
+
+    Ok(OutputInfo {
+        backend: handle.backend_name().to_string(),
+        sample_rate: format.map(|f| f.sample_rate),
+        channels: format.map(|f| f.channels),
+        config,
+    })
+}
+
+// Reopens the output on the chosen device; the choice is only saved once the
+// new stream is actually running.
+#[tauri::command]
+pub async fn set_output_device(
+    config: DeviceConfig,
+    handle: State<'_, AudioHandle>,
+) -> Result<OutputInfo, String> {
+    handle
+        .restart(&BackendKind::Device(config.clone()), &effects::current())
+        .map_err(|e| e.to_string())?;
+
+    state::set_output_device(&config).map_err(|e: AudioError| e.to_string())?;
+    println!("[AUDIO] Output switched to {:?}", config);
+
+    get_output_device(handle).await
+}
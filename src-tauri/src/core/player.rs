@@ -1,17 +1,128 @@
-use crate::engine::cache;
+use crate::core::effects;
+use crate::engine::controller::{self, DEFAULT_BEND_RANGE};
+use crate::engine::interpolate::{Interpolation, InterpolationCost};
+use crate::engine::mixer::MAX_MICS;
+use crate::engine::pedal;
+use crate::engine::stereo::{KeyPanning, StereoImage, MAX_WIDTH};
+use crate::engine::tuning::Tuning;
+use crate::engine::velocity::VelocityCurve;
 use crate::error::AudioError;
 use crate::setup::audio::AudioCommand;
-use crate::setup::audio::{self, AudioHandle};
+use crate::setup::audio::{self, AudioHandle, VoiceReport, LIVE_TURNS};
 use crate::setup::config::{AppState, InstrumentConfig};
 use crate::state;
 use lazy_static::lazy_static;
+use std::sync::atomic::{AtomicU8, Ordering};
 use std::sync::{Arc, Mutex};
-use tauri::{AppHandle, State}; 
+use tauri::{AppHandle, State};
 
 lazy_static! {
     pub static ref CURRENT_INSTRUMENT: Arc<Mutex<Option<InstrumentConfig>>> =
         Arc::new(Mutex::new(None));
     pub static ref CURRENT_FOLDER: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
+    // The user's curve wins over the instrument's; it belongs to the keyboard,
+    // not to the sample set.
+    static ref USER_VELOCITY_CURVE: Mutex<Option<VelocityCurve>> =
+        Mutex::new(state::read().ok().and_then(|s| s.velocity_curve));
+    // Same for interpolation: it trades CPU for fidelity on this machine.
+    static ref USER_INTERPOLATION: Mutex<Option<Interpolation>> =
+        Mutex::new(state::read().ok().and_then(|s| s.interpolation));
+    // And for the bend range, which follows the controller keyboard.
+    static ref USER_BEND_RANGE: Mutex<Option<f32>> =
+        Mutex::new(state::read().ok().and_then(|s| s.pitch_bend_range));
+    // Stereo placement the user picked over the instrument's.
+    static ref USER_KEY_PANNING: Mutex<Option<KeyPanning>> =
+        Mutex::new(state::read().ok().and_then(|s| s.key_panning));
+    static ref USER_STEREO_WIDTH: Mutex<Option<f32>> =
+        Mutex::new(state::read().ok().and_then(|s| s.stereo_width));
+    static ref TUNING: Mutex<Tuning> =
+        Mutex::new(state::read().map(|s| s.tuning).unwrap_or_default());
+}
+
+// Last CC67 value; layer and sample selection happen here, before the note
+// reaches the mixer, so the soft pedal has to be known on this side too.
+static SOFT_PEDAL: AtomicU8 = AtomicU8::new(0);
+
+// Last CC64 value, to play pedal noise when the pedal goes down or comes up.
+static SUSTAIN_PEDAL: AtomicU8 = AtomicU8::new(0);
+
+// Key each played key last sounded on, so its note-off reaches the right
+// voice even if the transpose changed while it was down.
+const NOT_SOUNDING: u8 = u8::MAX;
+
+static SOUNDING_KEY: [AtomicU8; 128] = [const { AtomicU8::new(NOT_SOUNDING) }; 128];
+
+pub fn tuning() -> Tuning {
+    *TUNING.lock().unwrap()
+}
+
+fn strike_key(midi: u8) -> Option<u8> {
+    let key = tuning().transpose_key(midi)?;
+    SOUNDING_KEY[midi as usize % 128].store(key, Ordering::Relaxed);
+    Some(key)
+}
+
+fn release_key(midi: u8) -> Option<u8> {
+    match SOUNDING_KEY[midi as usize % 128].swap(NOT_SOUNDING, Ordering::Relaxed) {
+        NOT_SOUNDING => tuning().transpose_key(midi),
+        key => Some(key),
+    }
+}
+
+fn soft_amount() -> f32 {
+    pedal::pedal_position(SOFT_PEDAL.load(Ordering::Relaxed))
+}
+
+pub fn velocity_curve(config: &InstrumentConfig) -> VelocityCurve {
+    USER_VELOCITY_CURVE
+        .lock()
+        .unwrap()
+        .clone()
+        .unwrap_or_else(|| config.velocity_curve())
+}
+
+fn bend_range() -> f32 {
+    let user = *USER_BEND_RANGE.lock().unwrap();
+    user.or_else(|| {
+        CURRENT_INSTRUMENT
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|c| c.pitch_bend_range())
+    })
+    .unwrap_or(DEFAULT_BEND_RANGE)
+}
+
+pub fn interpolation(config: &InstrumentConfig) -> Interpolation {
+    USER_INTERPOLATION
+        .lock()
+        .unwrap()
+        .unwrap_or_else(|| config.interpolation())
+}
+
+pub fn stereo_image(config: &InstrumentConfig) -> StereoImage {
+    let instrument = config.stereo_image();
+    StereoImage {
+        panning: USER_KEY_PANNING
+            .lock()
+            .unwrap()
+            .unwrap_or(instrument.panning),
+        width: USER_STEREO_WIDTH
+            .lock()
+            .unwrap()
+            .unwrap_or(instrument.width),
+    }
+}
+
+pub fn mic_levels(config: &InstrumentConfig) -> [f32; MAX_MICS] {
+    let folder = CURRENT_FOLDER.lock().unwrap().clone().unwrap_or_default();
+    audio::mic_levels(config, &folder)
+}
+
+fn apply_stereo_image(handle: &AudioHandle) {
+    if let Some(config) = CURRENT_INSTRUMENT.lock().unwrap().as_ref() {
+        handle.set_stereo_image(stereo_image(config));
+    }
 }
 
 #[tauri::command]
@@ -21,67 +132,17 @@ pub async fn play_note_auto(
     handle: State<'_, AudioHandle>,
     _app: AppHandle,
 ) -> Result<(), String> {
-    use crate::setup::audio::AudioCommand;
-
     let config_guard = CURRENT_INSTRUMENT.lock().unwrap();
     let config = config_guard.as_ref().ok_or("No instrument loaded")?;
 
-    let key_data = config
-        .piano_keys
-        .get(&midi_num.to_string())
-        .ok_or_else(|| format!("Note {} not found in piano configuration", midi_num))?;
-
-    let sample_idx: usize = {
-        let mut ranges: Vec<_> = config.general.layers.values().collect();
-        ranges.sort_by_key(|r| r.lovel);
-
-        let matched_layer = ranges
-            .iter()
-            .find(|r| velocity >= r.lovel && velocity <= r.hivel)
-            .map(|r| r.name.to_uppercase());
-
-        if let Some(layer_upper) = matched_layer {
-            key_data
-                .samples
-                .iter()
-                .position(|s| s.layer.to_uppercase() == layer_upper)
-                .unwrap_or(0)
-        } else {
-            let best = ranges
-                .iter()
-                .min_by_key(|r| {
-                    let mid = (r.lovel as i16 + r.hivel as i16) / 2;
-                    (velocity as i16 - mid).abs()
-                })
-                .map(|r| r.name.to_uppercase());
-
-            if let Some(layer_upper) = best {
-                key_data
-                    .samples
-                    .iter()
-                    .position(|s| s.layer.to_uppercase() == layer_upper)
-                    .unwrap_or(0)
-            } else {
-                0
-            }
-        }
+    let velocity = velocity_curve(config).apply(velocity);
+    let Some(key) = strike_key(midi_num) else {
+        return Ok(());
     };
+    let cmd = audio::note_command(config, key, velocity, None, soft_amount(), &LIVE_TURNS)
+        .map_err(|e| e.to_string())?;
 
-    let data = cache::get_by_index(midi_num, sample_idx)
-        .ok_or_else(|| format!("Sample not cached: midi={} idx={}", midi_num, sample_idx))?;
-
-    let recorded_midi = audio::pitch_to_midi(&key_data.pitch).unwrap_or(key_data.midi_num());
-    let ratio = audio::pitch_ratio(recorded_midi, midi_num);
-
-    handle
-        .cmd_tx
-        .try_send(AudioCommand::PlayNote {
-            midi: midi_num,
-            velocity,
-            data,
-            pitch_ratio: ratio,
-        })
-        .ok();
+    handle.send(cmd).ok();
 
     Ok(())
 }
@@ -141,6 +202,7 @@ pub async fn get_available_instruments_files(
 pub async fn load_instrument(
     folder: String,
     app: AppHandle,
+    handle: State<'_, AudioHandle>,
     _state: State<'_, AppState>,
 ) -> Result<crate::extra::sketch::instrument::response::InstrumentInfoResponse, String> {
     use crate::storage::handler::FileHandler;
@@ -167,6 +229,14 @@ pub async fn load_instrument(
     *CURRENT_INSTRUMENT.lock().unwrap() = Some(config.clone());
     *CURRENT_FOLDER.lock().unwrap() = Some(folder.clone());
 
+    effects::apply(&handle, &effects::for_instrument(&folder, &config));
+    handle.set_interpolation(interpolation(&config));
+    handle.set_voice_limit(config.polyphony(), config.steal_policy());
+    handle.set_mod_target(config.mod_target());
+    handle.set_resonance(config.resonance(), config.undamped_from());
+    handle.set_stereo_image(stereo_image(&config));
+    handle.set_mic_levels(audio::mic_levels(&config, &folder));
+
     let info = crate::extra::sketch::instrument::response::InstrumentInfoResponse::from_config(
         &config, &folder,
     );
@@ -181,6 +251,153 @@ pub async fn get_app_state() -> Result<AppState, String> {
     state::read().map_err(|e: AudioError| e.to_string())
 }
 
+#[tauri::command]
+pub async fn set_velocity_curve(curve: Option<VelocityCurve>) -> Result<(), String> {
+    state::set_velocity_curve(curve.clone()).map_err(|e: AudioError| e.to_string())?;
+    *USER_VELOCITY_CURVE.lock().unwrap() = curve;
+    Ok(())
+}
+
+// `None` hands the choice back to the loaded instrument.
+#[tauri::command]
+pub async fn set_interpolation(
+    mode: Option<Interpolation>,
+    handle: State<'_, AudioHandle>,
+) -> Result<(), String> {
+    state::set_interpolation(mode).map_err(|e: AudioError| e.to_string())?;
+    *USER_INTERPOLATION.lock().unwrap() = mode;
+
+    let resolved = match CURRENT_INSTRUMENT.lock().unwrap().as_ref() {
+        Some(config) => interpolation(config),
+        None => mode.unwrap_or_default(),
+    };
+    handle.set_interpolation(resolved);
+    Ok(())
+}
+
+// `None` goes back to the instrument's own setting.
+#[tauri::command]
+pub async fn set_key_panning(
+    mode: Option<KeyPanning>,
+    handle: State<'_, AudioHandle>,
+) -> Result<(), String> {
+    state::set_key_panning(mode).map_err(|e: AudioError| e.to_string())?;
+    *USER_KEY_PANNING.lock().unwrap() = mode;
+    apply_stereo_image(&handle);
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn set_stereo_width(
+    width: Option<f32>,
+    handle: State<'_, AudioHandle>,
+) -> Result<(), String> {
+    let width = width.map(|w| w.clamp(0.0, MAX_WIDTH));
+    state::set_stereo_width(width).map_err(|e: AudioError| e.to_string())?;
+    *USER_STEREO_WIDTH.lock().unwrap() = width;
+    apply_stereo_image(&handle);
+    Ok(())
+}
+
+#[derive(serde::Serialize)]
+pub struct MicLevel {
+    pub name: String,
+    pub level: f32,
+}
+
+#[tauri::command]
+pub async fn get_mic_levels() -> Result<Vec<MicLevel>, String> {
+    let config_guard = CURRENT_INSTRUMENT.lock().unwrap();
+    let config = config_guard.as_ref().ok_or("No instrument loaded")?;
+
+    let levels = mic_levels(config);
+    Ok(config
+        .mics()
+        .into_iter()
+        .zip(levels)
+        .map(|(mic, level)| MicLevel {
+            name: mic.name,
+            level,
+        })
+        .collect())
+}
+
+// Turning a mic position on from zero, or down to zero, reloads the
+// instrument so only audible positions stay in memory.
+#[tauri::command]
+pub async fn set_mic_level(
+    name: String,
+    level: f32,
+    app: AppHandle,
+    handle: State<'_, AudioHandle>,
+) -> Result<(), String> {
+    let folder = CURRENT_FOLDER
+        .lock()
+        .unwrap()
+        .clone()
+        .ok_or("No instrument loaded")?;
+    let config = CURRENT_INSTRUMENT
+        .lock()
+        .unwrap()
+        .clone()
+        .ok_or("No instrument loaded")?;
+    if !config.mics().iter().any(|mic| mic.name == name) {
+        return Err(format!("Unknown mic position '{}'", name));
+    }
+
+    let before = audio::mic_levels(&config, &folder);
+    state::set_mic_level(&folder, &name, level.clamp(0.0, 1.0))
+        .map_err(|e: AudioError| e.to_string())?;
+    let after = audio::mic_levels(&config, &folder);
+
+    let reload = before
+        .iter()
+        .zip(&after)
+        .any(|(old, new)| (*old > 0.0) != (*new > 0.0));
+    if reload {
+        let config =
+            audio::load_instrument_with_progress(&folder, &app).map_err(|e| e.to_string())?;
+        *CURRENT_INSTRUMENT.lock().unwrap() = Some(config);
+    }
+    handle.set_mic_levels(after);
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_tuning() -> Result<Tuning, String> {
+    Ok(tuning())
+}
+
+fn update_tuning(handle: &AudioHandle, change: impl FnOnce(&mut Tuning)) -> Result<(), String> {
+    let mut tuning = TUNING.lock().unwrap();
+    change(&mut tuning);
+    *tuning = tuning.clamped();
+    state::set_tuning(*tuning).map_err(|e: AudioError| e.to_string())?;
+    handle.set_tuning(tuning.ratio());
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn set_transpose(semitones: i32, handle: State<'_, AudioHandle>) -> Result<(), String> {
+    update_tuning(&handle, |t| t.transpose = semitones)
+}
+
+#[tauri::command]
+pub async fn set_fine_tune(cents: f32, handle: State<'_, AudioHandle>) -> Result<(), String> {
+    update_tuning(&handle, |t| t.fine_tune = cents)
+}
+
+// Frequency of A4 in Hz, e.g. 415, 432 or 442.
+#[tauri::command]
+pub async fn set_reference_pitch(hz: f32, handle: State<'_, AudioHandle>) -> Result<(), String> {
+    update_tuning(&handle, |t| t.reference = hz)
+}
+
+#[tauri::command]
+pub async fn get_interpolation_costs() -> Result<Vec<InterpolationCost>, String> {
+    Ok(Interpolation::ALL.iter().map(|m| m.measure()).collect())
+}
+
 #[tauri::command]
 pub async fn get_instrument_info(
 ) -> Result<Option<crate::extra::sketch::instrument::response::InstrumentInfoResponse>, String> {
@@ -205,44 +422,51 @@ pub fn set_current_folder(folder: String) {
     *CURRENT_FOLDER.lock().unwrap() = Some(folder);
 }
 
+#[derive(serde::Serialize)]
+pub struct EngineTime {
+    pub frame: u64,
+    pub sample_rate: u32,
+}
+
+// The engine frame playing now. Note commands take an optional `at` frame on
+// this clock and start exactly there, independent of buffer size and IPC
+// timing; without one they play as soon as possible.
+#[tauri::command]
+pub async fn get_engine_clock(handle: State<'_, AudioHandle>) -> Result<EngineTime, String> {
+    let clock = handle.clock();
+    Ok(EngineTime {
+        frame: clock.now(),
+        sample_rate: clock.sample_rate(),
+    })
+}
+
 #[tauri::command]
 pub async fn play_midi_note(
     midi_num: u8,
     velocity: u8,
     layer: String,
+    at: Option<u64>,
     handle: State<'_, AudioHandle>,
     _app: AppHandle,
 ) -> Result<(), String> {
     let config_guard = CURRENT_INSTRUMENT.lock().unwrap();
     let config = config_guard.as_ref().ok_or("No instrument loaded")?;
 
-    let key_data = config
-        .piano_keys
-        .get(&midi_num.to_string())
-        .ok_or_else(|| AudioError::NoteNotFound(midi_num).to_string())?;
-
-    let layer_upper = layer.to_uppercase();
-    let sample_idx = key_data
-        .samples
-        .iter()
-        .position(|s| s.layer.to_uppercase() == layer_upper)
-        .unwrap_or(0);
-
-    let data = cache::get_by_index(midi_num, sample_idx)
-        .ok_or_else(|| format!("Sample not cached: midi={} layer={}", midi_num, layer))?;
-
-    let recorded_midi = audio::pitch_to_midi(&key_data.pitch).unwrap_or(key_data.midi_num());
-    let ratio = audio::pitch_ratio(recorded_midi, midi_num);
-
-    handle
-        .cmd_tx
-        .try_send(AudioCommand::PlayNote {
-            midi: midi_num,
-            velocity,
-            data,
-            pitch_ratio: ratio,
-        })
-        .ok(); 
+    let velocity = velocity_curve(config).apply(velocity);
+    let Some(key) = strike_key(midi_num) else {
+        return Ok(());
+    };
+    let cmd = audio::note_command(
+        config,
+        key,
+        velocity,
+        Some(&layer),
+        soft_amount(),
+        &LIVE_TURNS,
+    )
+    .map_err(|e| e.to_string())?;
+
+    handle.send_at(at.unwrap_or(0), cmd).ok();
 
     Ok(())
 }
@@ -250,17 +474,99 @@ pub async fn play_midi_note(
 #[tauri::command]
 pub async fn stop_midi_note(
     midi_num: u8,
+    at: Option<u64>,
     handle: State<'_, AudioHandle>,
     _app: AppHandle,
     _state: State<'_, AppState>,
 ) -> Result<(), String> {
+    let Some(key) = release_key(midi_num) else {
+        return Ok(());
+    };
+    let release = CURRENT_INSTRUMENT
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(|config| audio::release_trigger(config, key, &LIVE_TURNS));
     handle
-        .cmd_tx
-        .try_send(AudioCommand::StopNote { midi: midi_num })
+        .send_at(
+            at.unwrap_or(0),
+            AudioCommand::StopNote { midi: key, release },
+        )
         .ok();
     Ok(())
 }
 
+#[tauri::command]
+pub async fn set_sustain(value: u8, handle: State<'_, AudioHandle>) -> Result<(), String> {
+    let down = pedal::is_pressed(value);
+    if pedal::is_pressed(SUSTAIN_PEDAL.swap(value, Ordering::Relaxed)) != down {
+        let noise = CURRENT_INSTRUMENT
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(|config| audio::pedal_noise(config, down));
+        if let Some(cmd) = noise {
+            handle.send(cmd).ok();
+        }
+    }
+    handle
+        .send(AudioCommand::Sustain { value })
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn set_sostenuto(value: u8, handle: State<'_, AudioHandle>) -> Result<(), String> {
+    handle
+        .send(AudioCommand::Sostenuto { value })
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn set_soft_pedal(value: u8, handle: State<'_, AudioHandle>) -> Result<(), String> {
+    SOFT_PEDAL.store(value, Ordering::Relaxed);
+    handle
+        .send(AudioCommand::Soft { value })
+        .map_err(|e| e.to_string())
+}
+
+// `value` is the 14-bit wheel position, 8192 at rest.
+#[tauri::command]
+pub async fn set_pitch_bend(value: u16, handle: State<'_, AudioHandle>) -> Result<(), String> {
+    let semitones = controller::bend_semitones(value, bend_range());
+    handle
+        .send(AudioCommand::PitchBend { semitones })
+        .map_err(|e| e.to_string())
+}
+
+// `None` hands the range back to the loaded instrument.
+#[tauri::command]
+pub async fn set_pitch_bend_range(semitones: Option<f32>) -> Result<(), String> {
+    let semitones = semitones.map(|s| s.clamp(0.0, 48.0));
+    state::set_pitch_bend_range(semitones).map_err(|e: AudioError| e.to_string())?;
+    *USER_BEND_RANGE.lock().unwrap() = semitones;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn set_mod_wheel(value: u8, handle: State<'_, AudioHandle>) -> Result<(), String> {
+    handle
+        .send(AudioCommand::ModWheel { value })
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn get_voice_stats(handle: State<'_, AudioHandle>) -> Result<VoiceReport, String> {
+    Ok(handle.voice_report())
+}
+
+// `volume` is linear, 0 to 1.
+#[tauri::command]
+pub async fn set_master_volume(volume: f32, handle: State<'_, AudioHandle>) -> Result<(), String> {
+    let volume = volume.clamp(0.0, 1.0);
+    handle.set_volume(volume);
+    state::set_master_volume(volume).map_err(|e: AudioError| e.to_string())
+}
+
 #[derive(serde::Deserialize)]
 pub struct BatchNote {
     pub midi_num: u8,
@@ -268,45 +574,36 @@ pub struct BatchNote {
     pub layer: String,
 }
 
+// Drops notes already sent ahead with `at` that have not started yet, so
+// pausing or seeking a song does not play on from the old position.
+#[tauri::command]
+pub async fn cancel_scheduled_notes(handle: State<'_, AudioHandle>) -> Result<(), String> {
+    handle
+        .send(AudioCommand::ClearPending)
+        .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 pub async fn play_notes_batch(
     notes: Vec<BatchNote>,
+    at: Option<u64>,
     handle: State<'_, AudioHandle>,
     _app: AppHandle,
 ) -> Result<(), String> {
     let config_guard = CURRENT_INSTRUMENT.lock().unwrap();
     let config = config_guard.as_ref().ok_or("No instrument loaded")?;
 
+    let soft = soft_amount();
+    let curve = velocity_curve(config);
     for note in notes {
-        let key_data = match config.piano_keys.get(&note.midi_num.to_string()) {
-            Some(k) => k,
-            None => continue, 
-        };
-
-        let layer_upper = note.layer.to_uppercase();
-        let sample_idx = key_data
-            .samples
-            .iter()
-            .position(|s| s.layer.to_uppercase() == layer_upper)
-            .unwrap_or(0);
-
-        let data = match cache::get_by_index(note.midi_num, sample_idx) {
-            Some(d) => d,
-            None => continue,
+        let layer = Some(note.layer.as_str());
+        let velocity = curve.apply(note.velocity);
+        let Some(key) = strike_key(note.midi_num) else {
+            continue;
         };
-
-        let recorded_midi = audio::pitch_to_midi(&key_data.pitch).unwrap_or(key_data.midi_num());
-        let ratio = audio::pitch_ratio(recorded_midi, note.midi_num);
-
-        handle
-            .cmd_tx
-            .try_send(AudioCommand::PlayNote {
-                midi: note.midi_num,
-                velocity: note.velocity,
-                data,
-                pitch_ratio: ratio,
-            })
-            .ok();
+        if let Ok(cmd) = audio::note_command(config, key, velocity, layer, soft, &LIVE_TURNS) {
+            handle.send_at(at.unwrap_or(0), cmd).ok();
+        }
     }
 
     Ok(())
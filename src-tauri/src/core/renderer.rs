@@ -0,0 +1,129 @@
+use crate::core::effects;
+use crate::core::player::{self, CURRENT_INSTRUMENT};
+use crate::core::visualizer::CURRENT_BUFFER;
+use crate::engine::render::{self, OfflineRenderer};
+use crate::engine::writer;
+use crate::error::AudioError;
+use crate::extra::challenge::buffer::{MidiBuffer, MidiNoteMs};
+use crate::extra::challenge::engine::decoder::MidiParser;
+use crate::state;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+const DEFAULT_SAMPLE_RATE: u32 = 48_000;
+
+// Rates the engine can render at; outside this, resampling and the
+// resonance model's delay lines stop making sense.
+const MIN_SAMPLE_RATE: u32 = 8_000;
+
+const MAX_SAMPLE_RATE: u32 = 192_000;
+
+const RENDER_CHANNELS: usize = 2;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RenderInfo {
+    pub output_path: String,
+    pub sample_rate: u32,
+    pub channels: usize,
+    pub duration_ms: u32,
+    pub note_count: usize,
+}
+
+async fn session_notes(file_path: Option<String>) -> Result<(Vec<MidiNoteMs>, String), String> {
+    use crate::storage::handler::FileHandler;
+
+    let Some(file_path) = file_path else {
+        let guard = CURRENT_BUFFER.lock().unwrap();
+        let buffer = guard.as_ref().ok_or("No session loaded")?;
+        return Ok((buffer.all_notes.clone(), buffer.file_path.clone()));
+    };
+
+    let file_handler = FileHandler::new().map_err(|e| e.to_string())?;
+
+    let song_exists = file_handler
+        .song_exists(&file_path)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if !song_exists {
+        return Err(format!("Song file '{}' does not exist", file_path));
+    }
+
+    let midi_file = MidiParser::parse_file(&file_path)
+        .map_err(|e| format!("Failed to parse MIDI file: {}", e))?;
+
+    let buffer = MidiBuffer::from_midi_file(&midi_file, file_path.clone());
+    Ok((buffer.all_notes, file_path))
+}
+
+fn default_output_path(source: &str) -> Result<PathBuf, AudioError> {
+    let stem = Path::new(source)
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "session".to_string());
+    Ok(state::renders_dir()?.join(format!("{}.wav", stem)))
+}
+
+#[tauri::command]
+pub async fn render_session(
+    file_path: Option<String>,
+    output_path: Option<String>,
+    sample_rate: Option<u32>,
+) -> Result<RenderInfo, String> {
+    let config = CURRENT_INSTRUMENT
+        .lock()
+        .unwrap()
+        .clone()
+        .ok_or("No instrument loaded")?;
+
+    let sample_rate = sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE);
+    if !(MIN_SAMPLE_RATE..=MAX_SAMPLE_RATE).contains(&sample_rate) {
+        return Err(format!(
+            "Sample rate {} Hz is outside {}-{} Hz",
+            sample_rate, MIN_SAMPLE_RATE, MAX_SAMPLE_RATE
+        ));
+    }
+
+    let (notes, source) = session_notes(file_path).await?;
+
+    let output = match output_path {
+        Some(path) => PathBuf::from(path),
+        None => default_output_path(&source).map_err(|e| e.to_string())?,
+    };
+    let note_count = notes.len();
+    let master = effects::current();
+    let interpolation = player::interpolation(&config);
+    let image = player::stereo_image(&config);
+    let mic_levels = player::mic_levels(&config);
+    let tuning = player::tuning();
+    let velocity_curve = player::velocity_curve(&config);
+
+    let (output, frames) = tokio::task::spawn_blocking(move || {
+        let mut renderer = OfflineRenderer::new(sample_rate, RENDER_CHANNELS);
+        renderer.set_effects(&master);
+        renderer.set_interpolation(interpolation);
+        renderer.set_stereo_image(image);
+        renderer.set_mic_levels(mic_levels);
+        renderer.set_tuning(tuning);
+        renderer.set_velocity_curve(velocity_curve);
+        let samples = render::render_notes(renderer, &config, &notes);
+        writer::write_wav(&output, &samples, sample_rate, RENDER_CHANNELS)?;
+        Ok::<_, AudioError>((output, samples.len() / RENDER_CHANNELS))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())?;
+
+    println!(
+        "[RENDER] Wrote {} notes to {:?} ({} frames @ {} Hz)",
+        note_count, output, frames, sample_rate
+    );
+
+    Ok(RenderInfo {
+        output_path: output.to_string_lossy().into_owned(),
+        sample_rate,
+        channels: RENDER_CHANNELS,
+        duration_ms: (frames as u64 * 1000 / sample_rate as u64) as u32,
+        note_count,
+    })
+}
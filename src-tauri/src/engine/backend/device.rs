@@ -0,0 +1,240 @@
+use super::{AudioBackend, RenderFn, StreamFormat};
+use crate::error::{AudioError, Result};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{BufferSize, SampleFormat, SupportedBufferSize, SupportedStreamConfig};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+
+// The user's output choice. Unset fields fall back to the host's defaults;
+// `device` is a cpal device id ("host:id"), stable across restarts.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeviceConfig {
+    pub host: Option<String>,
+    pub device: Option<String>,
+    pub sample_rate: Option<u32>,
+    pub buffer_size: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigRange {
+    pub channels: u16,
+    pub min_sample_rate: u32,
+    pub max_sample_rate: u32,
+    pub min_buffer_size: Option<u32>,
+    pub max_buffer_size: Option<u32>,
+    pub sample_format: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceInfo {
+    pub id: String,
+    pub name: String,
+    pub is_default: bool,
+    pub configs: Vec<ConfigRange>,
+}
+
+pub fn list_hosts() -> Vec<String> {
+    cpal::available_hosts()
+        .into_iter()
+        .map(|id| id.name().to_string())
+        .collect()
+}
+
+fn find_host(name: Option<&str>) -> Result<cpal::Host> {
+    let Some(name) = name else {
+        return Ok(cpal::default_host());
+    };
+    let id = cpal::available_hosts()
+        .into_iter()
+        .find(|id| id.name().eq_ignore_ascii_case(name))
+        .ok_or_else(|| AudioError::StreamError(format!("Audio host '{}' not available", name)))?;
+    cpal::host_from_id(id).map_err(|e| AudioError::StreamError(e.to_string()))
+}
+
+fn device_id(device: &cpal::Device) -> Option<String> {
+    device.id().ok().map(|id| id.to_string())
+}
+
+fn device_name(device: &cpal::Device) -> String {
+    device
+        .description()
+        .map(|d| d.name().to_string())
+        .unwrap_or_else(|_| "Unknown device".to_string())
+}
+
+pub fn list_devices(host: Option<&str>) -> Result<Vec<DeviceInfo>> {
+    let host = find_host(host)?;
+    let default_id = host.default_output_device().as_ref().and_then(device_id);
+
+    let devices = host
+        .output_devices()
+        .map_err(|e| AudioError::StreamError(format!("Cannot list output devices: {}", e)))?;
+
+    Ok(devices
+        .filter_map(|device| {
+            let id = device_id(&device)?;
+            let configs = device
+                .supported_output_configs()
+                .map(|configs| {
+                    configs
+                        .map(|c| {
+                            let (min_buffer_size, max_buffer_size) = match c.buffer_size() {
+                                SupportedBufferSize::Range { min, max } => (Some(*min), Some(*max)),
+                                SupportedBufferSize::Unknown => (None, None),
+                            };
+                            ConfigRange {
+                                channels: c.channels(),
+                                min_sample_rate: c.min_sample_rate(),
+                                max_sample_rate: c.max_sample_rate(),
+                                min_buffer_size,
+                                max_buffer_size,
+                                sample_format: c.sample_format().to_string(),
+                            }
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+
+            Some(DeviceInfo {
+                is_default: default_id.as_deref() == Some(id.as_str()),
+                name: device_name(&device),
+                id,
+                configs,
+            })
+        })
+        .collect())
+}
+
+fn find_device(config: &DeviceConfig) -> Result<cpal::Device> {
+    let host = find_host(config.host.as_deref())?;
+    match config.device.as_deref() {
+        Some(id) => {
+            let parsed = id
+                .parse()
+                .map_err(|_| AudioError::StreamError(format!("Invalid device id '{}'", id)))?;
+            host.device_by_id(&parsed)
+                .ok_or_else(|| AudioError::StreamError(format!("Output device '{}' not found", id)))
+        }
+        None => host
+            .default_output_device()
+            .ok_or(AudioError::NoOutputDevice),
+    }
+}
+
+// Picks a supported config at the requested rate, preferring f32 output, or
+// the device default when no rate is requested.
+fn find_stream_config(
+    device: &cpal::Device,
+    config: &DeviceConfig,
+) -> Result<SupportedStreamConfig> {
+    let default = device.default_output_config()?;
+    let Some(rate) = config.sample_rate else {
+        return Ok(default);
+    };
+
+    let ranges: Vec<_> = device
+        .supported_output_configs()
+        .map_err(|e| AudioError::StreamError(format!("Cannot query device configs: {}", e)))?
+        .filter(|c| rate >= c.min_sample_rate() && rate <= c.max_sample_rate())
+        .collect();
+
+    ranges
+        .iter()
+        .find(|c| c.sample_format() == SampleFormat::F32 && c.channels() == default.channels())
+        .or_else(|| {
+            ranges
+                .iter()
+                .find(|c| c.sample_format() == SampleFormat::F32)
+        })
+        .or_else(|| ranges.first())
+        .map(|c| c.with_sample_rate(rate))
+        .ok_or_else(|| AudioError::StreamError(format!("Device does not support {} Hz", rate)))
+}
+
+fn buffer_size(supported: &SupportedStreamConfig, requested: Option<u32>) -> BufferSize {
+    match (requested, supported.buffer_size()) {
+        (Some(frames), SupportedBufferSize::Range { min, max }) => {
+            BufferSize::Fixed(frames.clamp(*min, *max))
+        }
+        (Some(frames), SupportedBufferSize::Unknown) => BufferSize::Fixed(frames),
+        (None, _) => BufferSize::Default,
+    }
+}
+
+pub struct DeviceBackend {
+    format: StreamFormat,
+    failure: Arc<Mutex<Option<String>>>,
+    _stream: cpal::Stream,
+}
+
+impl DeviceBackend {
+    pub fn start(
+        config: &DeviceConfig,
+        make_render: impl FnOnce(StreamFormat) -> RenderFn,
+    ) -> Result<Self> {
+        let device = find_device(config)?;
+        let supported = find_stream_config(&device, config)?;
+
+        let format = StreamFormat {
+            sample_rate: supported.sample_rate(),
+            channels: supported.channels() as usize,
+        };
+        let mut render = make_render(format);
+
+        let mut stream_config: cpal::StreamConfig = supported.config();
+        stream_config.buffer_size = buffer_size(&supported, config.buffer_size);
+
+        // Underruns are glitches the stream recovers from by itself; anything
+        // else means the device or sound server went away.
+        let failure = Arc::new(Mutex::new(None));
+        let failure_slot = Arc::clone(&failure);
+
+        let stream = device
+            .build_output_stream(
+                &stream_config,
+                move |output: &mut [f32], _| render(output),
+                move |err| {
+                    eprintln!("[AUDIO] Stream error: {}", err);
+                    if !matches!(err, cpal::StreamError::BufferUnderrun) {
+                        failure_slot
+                            .lock()
+                            .unwrap()
+                            .get_or_insert_with(|| err.to_string());
+                    }
+                },
+                None,
+            )
+            .map_err(AudioError::BuildStreamError)?;
+
+        stream.play().map_err(AudioError::PlayStreamError)?;
+
+        println!(
+            "[AUDIO] Opened {} @ {} Hz, {} ch, buffer {:?}",
+            device_name(&device),
+            format.sample_rate,
+            format.channels,
+            stream_config.buffer_size
+        );
+
+        Ok(Self {
+            format,
+            failure,
+            _stream: stream,
+        })
+    }
+}
+
+impl AudioBackend for DeviceBackend {
+    fn name(&self) -> &'static str {
+        "device"
+    }
+
+    fn format(&self) -> StreamFormat {
+        self.format
+    }
+
+    fn failure(&self) -> Option<String> {
+        self.failure.lock().unwrap().clone()
+    }
+}
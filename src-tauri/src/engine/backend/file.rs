@@ -0,0 +1,125 @@
+use super::{AudioBackend, PacedThread, RenderFn, StreamFormat};
+use crate::engine::writer::WavWriter;
+use crate::error::{AudioError, Result};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+type SharedWriter = Arc<Mutex<Option<WavWriter<BufWriter<File>>>>>;
+
+// Records everything the engine plays into a WAV file, in real time.
+pub struct FileBackend {
+    format: StreamFormat,
+    path: PathBuf,
+    writer: SharedWriter,
+    thread: PacedThread,
+}
+
+impl FileBackend {
+    pub fn start(path: &Path, format: StreamFormat, render: RenderFn) -> Result<Self> {
+        let writer: SharedWriter = Arc::new(Mutex::new(Some(WavWriter::create(
+            path,
+            format.sample_rate,
+            format.channels,
+        )?)));
+        let sink_writer = Arc::clone(&writer);
+
+        let thread = PacedThread::spawn("rakund-file-audio", format, render, move |block| {
+            if let Some(w) = sink_writer.lock().unwrap().as_mut() {
+                if let Err(e) = w.write(block) {
+                    eprintln!("[AUDIO] File sink write failed: {}", e);
+                }
+            }
+        })
+        .map_err(|e| AudioError::StreamError(format!("Cannot start file sink: {}", e)))?;
+
+        Ok(Self {
+            format,
+            path: path.to_path_buf(),
+            writer,
+            thread,
+        })
+    }
+}
+
+impl AudioBackend for FileBackend {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    fn format(&self) -> StreamFormat {
+        self.format
+    }
+}
+
+impl Drop for FileBackend {
+    fn drop(&mut self) {
+        self.thread.stop();
+        if let Some(writer) = self.writer.lock().unwrap().take() {
+            if let Err(e) = writer.finish() {
+                eprintln!("[AUDIO] Cannot finalize {:?}: {}", self.path, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::backend::BackendKind;
+    use crate::engine::effects::MasterParams;
+    use crate::engine::envelope::EnvelopeParams;
+    use crate::engine::mixer::AudioCommand;
+    use crate::engine::sample::AudioSample;
+    use crate::setup::audio;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_file_backend_records_notes() {
+        let path = std::env::temp_dir().join(format!("rakund-{}.wav", uuid::Uuid::new_v4()));
+        let handle = audio::start_stream_with(&BackendKind::File(path.clone())).unwrap();
+        assert_eq!(handle.backend_name(), "file");
+
+        let sample = Arc::new(AudioSample::new(vec![0.5; 48_000], 48_000, 1));
+        handle
+            .send(AudioCommand::PlayNote {
+                midi: 60,
+                velocity: 127,
+                sample,
+                pitch_ratio: 1.0,
+                envelope: EnvelopeParams::default(),
+                blend: None,
+                mic: 0,
+                mics: Default::default(),
+            })
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        drop(handle);
+
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        let data_len = u32::from_le_bytes([bytes[40], bytes[41], bytes[42], bytes[43]]);
+        assert_eq!(data_len as usize, bytes.len() - 44);
+        assert!(bytes[44..].iter().any(|b| *b != 0));
+    }
+
+    #[test]
+    fn test_restart_moves_output_to_new_backend() {
+        let path = std::env::temp_dir().join(format!("rakund-{}.wav", uuid::Uuid::new_v4()));
+        let handle = audio::start_stream_with(&BackendKind::Null).unwrap();
+        assert_eq!(handle.backend_name(), "null");
+
+        handle
+            .restart(&BackendKind::File(path.clone()), &MasterParams::default())
+            .unwrap();
+        assert_eq!(handle.backend_name(), "file");
+        std::thread::sleep(Duration::from_millis(50));
+        drop(handle);
+
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert!(bytes.len() > 44);
+    }
+}
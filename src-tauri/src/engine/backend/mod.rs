@@ -0,0 +1,163 @@
+pub mod device;
+pub mod file;
+pub mod null;
+
+use crate::error::Result;
+use device::DeviceConfig;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+pub const BACKEND_ENV: &str = "RAKUND_AUDIO_BACKEND";
+
+const HEADLESS_SAMPLE_RATE: u32 = 48_000;
+
+const HEADLESS_CHANNELS: usize = 2;
+
+const HEADLESS_BLOCK_FRAMES: usize = 512;
+
+pub type RenderFn = Box<dyn FnMut(&mut [f32]) + Send + 'static>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamFormat {
+    pub sample_rate: u32,
+    pub channels: usize,
+}
+
+impl Default for StreamFormat {
+    fn default() -> Self {
+        Self {
+            sample_rate: HEADLESS_SAMPLE_RATE,
+            channels: HEADLESS_CHANNELS,
+        }
+    }
+}
+
+// A running output. Dropping it stops the audio.
+pub trait AudioBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn format(&self) -> StreamFormat;
+
+    // The error that killed the output, once it needs to be rebuilt.
+    fn failure(&self) -> Option<String> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BackendKind {
+    Device(DeviceConfig),
+    Null,
+    File(PathBuf),
+}
+
+impl BackendKind {
+    // RAKUND_AUDIO_BACKEND=null | file:<path.wav> | device (default)
+    pub fn from_env() -> Self {
+        match std::env::var(BACKEND_ENV) {
+            Ok(value) => Self::parse(&value),
+            Err(_) => Self::Device(DeviceConfig::default()),
+        }
+    }
+
+    pub fn parse(value: &str) -> Self {
+        let value = value.trim();
+        if value.eq_ignore_ascii_case("null") || value.eq_ignore_ascii_case("none") {
+            Self::Null
+        } else if let Some(path) = value.strip_prefix("file:") {
+            Self::File(PathBuf::from(path))
+        } else {
+            Self::Device(DeviceConfig::default())
+        }
+    }
+}
+
+// `make_render` receives the negotiated format so the caller can size its
+// mixer before the first callback runs.
+pub fn start(
+    kind: &BackendKind,
+    make_render: impl FnOnce(StreamFormat) -> RenderFn,
+) -> Result<Box<dyn AudioBackend>> {
+    match kind {
+        BackendKind::Device(config) => {
+            Ok(Box::new(device::DeviceBackend::start(config, make_render)?))
+        }
+        BackendKind::Null => {
+            let format = StreamFormat::default();
+            Ok(Box::new(null::NullBackend::start(
+                format,
+                make_render(format),
+            )?))
+        }
+        BackendKind::File(path) => {
+            let format = StreamFormat::default();
+            Ok(Box::new(file::FileBackend::start(
+                path,
+                format,
+                make_render(format),
+            )?))
+        }
+    }
+}
+
+// Pulls blocks from the render callback at wall-clock pace on its own thread,
+// standing in for a device clock when there is no hardware.
+pub struct PacedThread {
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl PacedThread {
+    pub fn spawn(
+        name: &str,
+        format: StreamFormat,
+        mut render: RenderFn,
+        mut sink: impl FnMut(&[f32]) + Send + 'static,
+    ) -> std::io::Result<Self> {
+        let running = Arc::new(AtomicBool::new(true));
+        let flag = Arc::clone(&running);
+
+        let block_time =
+            Duration::from_secs_f64(HEADLESS_BLOCK_FRAMES as f64 / format.sample_rate as f64);
+
+        let thread = std::thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || {
+                let mut block = vec![0.0f32; HEADLESS_BLOCK_FRAMES * format.channels];
+                let mut deadline = Instant::now();
+
+                while flag.load(Ordering::Acquire) {
+                    render(&mut block);
+                    sink(&block);
+
+                    deadline += block_time;
+                    let now = Instant::now();
+                    if deadline > now {
+                        std::thread::sleep(deadline - now);
+                    } else {
+                        deadline = now;
+                    }
+                }
+            })?;
+
+        Ok(Self {
+            running,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn stop(&mut self) {
+        self.running.store(false, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for PacedThread {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
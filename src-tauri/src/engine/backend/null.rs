@@ -0,0 +1,30 @@
+use super::{AudioBackend, PacedThread, RenderFn, StreamFormat};
+use crate::error::{AudioError, Result};
+
+// Renders and discards audio so commands and voices behave as on a device.
+pub struct NullBackend {
+    format: StreamFormat,
+    _thread: PacedThread,
+}
+
+impl NullBackend {
+    pub fn start(format: StreamFormat, render: RenderFn) -> Result<Self> {
+        let thread = PacedThread::spawn("rakund-null-audio", format, render, |_| {})
+            .map_err(|e| AudioError::StreamError(format!("Cannot start null sink: {}", e)))?;
+
+        Ok(Self {
+            format,
+            _thread: thread,
+        })
+    }
+}
+
+impl AudioBackend for NullBackend {
+    fn name(&self) -> &'static str {
+        "null"
+    }
+
+    fn format(&self) -> StreamFormat {
+        self.format
+    }
+}
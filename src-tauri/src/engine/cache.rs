@@ -1,8 +1,9 @@
+use crate::engine::sample::AudioSample;
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
 lazy_static::lazy_static! {
-    pub static ref SAMPLE_CACHE: Arc<Mutex<HashMap<String, Arc<Vec<f32>>>>> =
+    pub static ref SAMPLE_CACHE: Arc<Mutex<HashMap<String, Arc<AudioSample>>>> =
         Arc::new(Mutex::new(HashMap::new()));
 }
 
@@ -10,14 +11,14 @@ fn key_by_index(midi: u8, layer_idx: usize) -> String {
     format!("{}:{}", midi, layer_idx)
 }
 
-pub fn insert_by_index(midi: u8, layer_idx: usize, data: Arc<Vec<f32>>) {
+pub fn insert_by_index(midi: u8, layer_idx: usize, data: Arc<AudioSample>) {
     SAMPLE_CACHE
         .lock()
         .unwrap()
         .insert(key_by_index(midi, layer_idx), data);
 }
 
-pub fn get_by_index(midi: u8, layer_idx: usize) -> Option<Arc<Vec<f32>>> {
+pub fn get_by_index(midi: u8, layer_idx: usize) -> Option<Arc<AudioSample>> {
     SAMPLE_CACHE
         .lock()
         .unwrap()
@@ -25,6 +26,70 @@ pub fn get_by_index(midi: u8, layer_idx: usize) -> Option<Arc<Vec<f32>>> {
         .cloned()
 }
 
+fn mic_key(midi: u8, mic: usize, idx: usize) -> String {
+    format!("{}:mic{}:{}", midi, mic, idx)
+}
+
+// Slot 0 is the main mic, stored like any other sample.
+pub fn insert_mic(midi: u8, mic: usize, idx: usize, data: Arc<AudioSample>) {
+    if mic == 0 {
+        return insert_by_index(midi, idx, data);
+    }
+    SAMPLE_CACHE
+        .lock()
+        .unwrap()
+        .insert(mic_key(midi, mic, idx), data);
+}
+
+pub fn get_mic(midi: u8, mic: usize, idx: usize) -> Option<Arc<AudioSample>> {
+    if mic == 0 {
+        return get_by_index(midi, idx);
+    }
+    SAMPLE_CACHE
+        .lock()
+        .unwrap()
+        .get(&mic_key(midi, mic, idx))
+        .cloned()
+}
+
+fn release_key(midi: u8, idx: usize) -> String {
+    format!("{}:release:{}", midi, idx)
+}
+
+pub fn insert_release(midi: u8, idx: usize, data: Arc<AudioSample>) {
+    SAMPLE_CACHE
+        .lock()
+        .unwrap()
+        .insert(release_key(midi, idx), data);
+}
+
+pub fn get_release(midi: u8, idx: usize) -> Option<Arc<AudioSample>> {
+    SAMPLE_CACHE
+        .lock()
+        .unwrap()
+        .get(&release_key(midi, idx))
+        .cloned()
+}
+
+fn pedal_key(down: bool, idx: usize) -> String {
+    format!("pedal:{}:{}", if down { "down" } else { "up" }, idx)
+}
+
+pub fn insert_pedal(down: bool, idx: usize, data: Arc<AudioSample>) {
+    SAMPLE_CACHE
+        .lock()
+        .unwrap()
+        .insert(pedal_key(down, idx), data);
+}
+
+pub fn get_pedal(down: bool, idx: usize) -> Option<Arc<AudioSample>> {
+    SAMPLE_CACHE
+        .lock()
+        .unwrap()
+        .get(&pedal_key(down, idx))
+        .cloned()
+}
+
 pub fn clear() {
     SAMPLE_CACHE.lock().unwrap().clear();
 }
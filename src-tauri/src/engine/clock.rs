@@ -0,0 +1,67 @@
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::Instant;
+
+// The output's frame counter, published by the audio callback so commands can
+// be stamped with the frame they should land on. Frames are counted at the
+// stream's rate and keep running across stream restarts.
+pub struct EngineClock {
+    epoch: Instant,
+    frame: AtomicU64,
+    // When `frame` was published, in nanoseconds since `epoch`.
+    published_at: AtomicU64,
+    sample_rate: AtomicU32,
+}
+
+impl Default for EngineClock {
+    fn default() -> Self {
+        Self {
+            epoch: Instant::now(),
+            frame: AtomicU64::new(0),
+            published_at: AtomicU64::new(0),
+            sample_rate: AtomicU32::new(0),
+        }
+    }
+}
+
+impl EngineClock {
+    // Called at the start of every buffer; neither locks nor allocates.
+    pub fn publish(&self, frame: u64, sample_rate: u32) {
+        let nanos = self.epoch.elapsed().as_nanos() as u64;
+        self.frame.store(frame, Ordering::Relaxed);
+        self.published_at.store(nanos, Ordering::Relaxed);
+        self.sample_rate.store(sample_rate, Ordering::Relaxed);
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame.load(Ordering::Relaxed)
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate.load(Ordering::Relaxed)
+    }
+
+    // The frame being rendered about now, extrapolated from the last buffer.
+    // Commands stamped with it play as soon as the next buffer starts.
+    pub fn now(&self) -> u64 {
+        let frame = self.frame();
+        let published_at = self.published_at.load(Ordering::Relaxed);
+        let elapsed = (self.epoch.elapsed().as_nanos() as u64).saturating_sub(published_at);
+        frame + elapsed * self.sample_rate() as u64 / 1_000_000_000
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clock_extrapolates_from_last_publish() {
+        let clock = EngineClock::default();
+        clock.publish(48_000, 48_000);
+        std::thread::sleep(std::time::Duration::from_millis(20));
+
+        let now = clock.now();
+        assert_eq!(clock.frame(), 48_000);
+        assert!((48_960..60_000).contains(&now));
+    }
+}
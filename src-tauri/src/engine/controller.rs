@@ -0,0 +1,178 @@
+use crate::engine::mixer::MAX_BLOCK_FRAMES;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::TAU;
+
+pub const PITCH_BEND_CENTER: u16 = 8192;
+
+pub const DEFAULT_BEND_RANGE: f32 = 2.0;
+
+const SMOOTHING_SECS: f32 = 0.01;
+
+const VIBRATO_HZ: f32 = 5.5;
+
+// Vibrato depth with the wheel all the way up.
+const MAX_VIBRATO_SEMITONES: f32 = 0.5;
+
+// The filter sweeps exponentially from open to this cutoff.
+const FILTER_OPEN_HZ: f32 = 20_000.0;
+
+const FILTER_CLOSED_HZ: f32 = 400.0;
+
+// What the mod wheel (CC1) drives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModTarget {
+    #[default]
+    Vibrato,
+    Filter,
+}
+
+impl ModTarget {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "vibrato" => Some(Self::Vibrato),
+            "filter" => Some(Self::Filter),
+            _ => None,
+        }
+    }
+}
+
+// Semitones for a 14-bit pitch wheel position, `range` semitones at either end.
+pub fn bend_semitones(value: u16, range: f32) -> f32 {
+    let offset = value.min(16_383) as f32 - PITCH_BEND_CENTER as f32;
+    offset / PITCH_BEND_CENTER as f32 * range
+}
+
+// Pitch wheel and mod wheel state shared by every voice. `advance` fills
+// per-frame pitch ratios and filter coefficients for the next block; both
+// wheels are smoothed so controller steps do not zipper.
+pub struct Modulation {
+    target: ModTarget,
+    sample_rate: f32,
+    smoothing: f32,
+    bend: f32,
+    bend_target: f32,
+    wheel: f32,
+    wheel_target: f32,
+    lfo_phase: f32,
+    pitch: Vec<f32>,
+    coeff: Vec<f32>,
+    filter_state: Vec<f32>,
+}
+
+impl Modulation {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        let sample_rate = sample_rate as f32;
+        Self {
+            target: ModTarget::default(),
+            sample_rate,
+            smoothing: (-1.0 / (SMOOTHING_SECS * sample_rate)).exp(),
+            bend: 1.0,
+            bend_target: 1.0,
+            wheel: 0.0,
+            wheel_target: 0.0,
+            lfo_phase: 0.0,
+            pitch: vec![1.0; MAX_BLOCK_FRAMES],
+            coeff: vec![1.0; MAX_BLOCK_FRAMES],
+            filter_state: vec![0.0; channels.max(1)],
+        }
+    }
+
+    pub fn set_target(&mut self, target: ModTarget) {
+        self.target = target;
+    }
+
+    pub fn set_bend(&mut self, semitones: f32) {
+        self.bend_target = 2.0f32.powf(semitones / 12.0);
+    }
+
+    pub fn set_wheel(&mut self, value: u8) {
+        self.wheel_target = value.min(127) as f32 / 127.0;
+    }
+
+    fn is_idle(&self) -> bool {
+        self.bend == 1.0 && self.bend_target == 1.0 && self.wheel == 0.0 && self.wheel_target == 0.0
+    }
+
+    pub fn advance(&mut self, frames: usize) {
+        let frames = frames.min(MAX_BLOCK_FRAMES);
+        if self.is_idle() {
+            self.pitch[..frames].fill(1.0);
+            self.coeff[..frames].fill(1.0);
+            return;
+        }
+
+        let lfo_step = TAU * VIBRATO_HZ / self.sample_rate;
+        for i in 0..frames {
+            self.bend = self.bend_target + (self.bend - self.bend_target) * self.smoothing;
+            self.wheel = self.wheel_target + (self.wheel - self.wheel_target) * self.smoothing;
+            if (self.bend - self.bend_target).abs() < 1e-4 {
+                self.bend = self.bend_target;
+            }
+            if (self.wheel - self.wheel_target).abs() < 1e-4 {
+                self.wheel = self.wheel_target;
+            }
+
+            let (vibrato, cutoff) = match self.target {
+                ModTarget::Vibrato => {
+                    let depth = MAX_VIBRATO_SEMITONES * self.wheel * self.lfo_phase.sin();
+                    (2.0f32.powf(depth / 12.0), FILTER_OPEN_HZ)
+                }
+                ModTarget::Filter => (
+                    1.0,
+                    FILTER_OPEN_HZ * (FILTER_CLOSED_HZ / FILTER_OPEN_HZ).powf(self.wheel),
+                ),
+            };
+            self.lfo_phase = (self.lfo_phase + lfo_step) % TAU;
+
+            self.pitch[i] = self.bend * vibrato;
+            self.coeff[i] = if cutoff >= FILTER_OPEN_HZ {
+                1.0
+            } else {
+                1.0 - (-TAU * cutoff / self.sample_rate).exp()
+            };
+        }
+    }
+
+    // Pitch ratio for each frame of the block set up by `advance`.
+    pub fn pitch(&self, frames: usize) -> &[f32] {
+        &self.pitch[..frames.min(MAX_BLOCK_FRAMES)]
+    }
+
+    // One-pole lowpass over the block; a coefficient of 1 passes it untouched.
+    pub fn filter(&mut self, mix: &mut [f32], channels: usize) {
+        for (frame, coeff) in mix.chunks_exact_mut(channels).zip(&self.coeff) {
+            for (sample, state) in frame.iter_mut().zip(self.filter_state.iter_mut()) {
+                if *coeff >= 1.0 {
+                    *state = *sample;
+                } else {
+                    *state += (*sample - *state) * coeff;
+                    *sample = *state;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bend_range_and_smoothing() {
+        assert_eq!(bend_semitones(PITCH_BEND_CENTER, 2.0), 0.0);
+        assert_eq!(bend_semitones(0, 2.0), -2.0);
+        assert!((bend_semitones(16_383, 12.0) - 12.0).abs() < 0.01);
+
+        let mut modulation = Modulation::new(48_000, 1);
+        modulation.set_bend(12.0);
+        modulation.advance(64);
+        let first = modulation.pitch(64)[0];
+        assert!(first > 1.0 && first < 1.1);
+
+        for _ in 0..100 {
+            modulation.advance(64);
+        }
+        assert_eq!(modulation.pitch(64)[63], 2.0);
+    }
+}
@@ -1,4 +1,6 @@
+use crate::engine::sample::AudioSample;
 use crate::error::{AudioError, Result};
+use std::io::{Read, Seek, SeekFrom};
 use std::sync::Arc;
 use symphonia::core::audio::SampleBuffer;
 use symphonia::core::codecs::DecoderOptions;
@@ -7,8 +9,14 @@ use symphonia::core::io::MediaSourceStream;
 use symphonia::core::meta::MetadataOptions;
 use symphonia::core::probe::Hint;
 
-pub fn decode(path: &str) -> Result<Arc<Vec<f32>>> {
-    let file = std::fs::File::open(path)
+pub fn decode(path: &str) -> Result<Arc<AudioSample>> {
+    let mut file = std::fs::File::open(path)
+        .map_err(|e| AudioError::FlacDecodeError(path.to_string(), e.to_string()))?;
+
+    // Loop points come from the same open file, read before the decoder
+    // takes it over.
+    let loop_points = smpl_loop(&mut file);
+    file.rewind()
         .map_err(|e| AudioError::FlacDecodeError(path.to_string(), e.to_string()))?;
 
     let mss = MediaSourceStream::new(Box::new(file), Default::default());
@@ -47,6 +55,8 @@ pub fn decode(path: &str) -> Result<Arc<Vec<f32>>> {
 
     let mut samples: Vec<f32> = Vec::new();
     let mut sample_buf: Option<SampleBuffer<f32>> = None;
+    let mut sample_rate = 0u32;
+    let mut channels = 0usize;
 
     loop {
         let packet = match format.next_packet() {
@@ -67,9 +77,12 @@ pub fn decode(path: &str) -> Result<Arc<Vec<f32>>> {
             .decode(&packet)
             .map_err(|e| AudioError::FlacDecodeError(path.to_string(), e.to_string()))?;
 
-        let buf = sample_buf.get_or_insert_with(|| {
-            SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec())
-        });
+        let spec = *decoded.spec();
+        sample_rate = spec.rate;
+        channels = spec.channels.count();
+
+        let buf = sample_buf
+            .get_or_insert_with(|| SampleBuffer::<f32>::new(decoded.capacity() as u64, spec));
 
         buf.copy_interleaved_ref(decoded);
         samples.extend_from_slice(buf.samples());
@@ -82,5 +95,128 @@ pub fn decode(path: &str) -> Result<Arc<Vec<f32>>> {
         ));
     }
 
-    Ok(Arc::new(samples))
+    let mut sample = AudioSample::new(samples, sample_rate, channels);
+    if let Some((start, end)) = loop_points {
+        sample.set_loop(start, end, 0);
+    }
+
+    Ok(Arc::new(sample))
+}
+
+// First forward loop in a RIFF file's `smpl` chunk, as start and exclusive
+// end frame. Non-WAV files and WAVs without loops give `None`.
+pub fn smpl_loop<R: Read + Seek>(reader: &mut R) -> Option<(usize, usize)> {
+    let mut header = [0u8; 12];
+    reader.read_exact(&mut header).ok()?;
+    if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+        return None;
+    }
+
+    let u32_at = |bytes: &[u8], at: usize| -> u32 {
+        u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+    };
+
+    let mut chunk = [0u8; 8];
+    while reader.read_exact(&mut chunk).is_ok() {
+        let size = u32_at(&chunk, 4) as usize;
+        if &chunk[0..4] != b"smpl" {
+            // Chunks are padded to an even length.
+            reader
+                .seek(SeekFrom::Current((size + size % 2) as i64))
+                .ok()?;
+            continue;
+        }
+
+        // Only the header and loop entries are read, never more than the
+        // chunk claims, so a corrupt size cannot force a huge allocation.
+        let mut body = reader.by_ref().take(size as u64);
+        let mut smpl = [0u8; 36];
+        body.read_exact(&mut smpl).ok()?;
+        let loops = u32_at(&smpl, 28);
+        let mut entry = [0u8; 24];
+        for _ in 0..loops {
+            body.read_exact(&mut entry).ok()?;
+            if u32_at(&entry, 4) == 0 {
+                let start = u32_at(&entry, 8) as usize;
+                // The chunk stores the last frame of the loop.
+                let end = u32_at(&entry, 12) as usize + 1;
+                return Some((start, end));
+            }
+        }
+        return None;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut out = id.to_vec();
+        out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        out.extend_from_slice(body);
+        if body.len() % 2 == 1 {
+            out.push(0);
+        }
+        out
+    }
+
+    #[test]
+    fn test_smpl_loop_skips_other_chunks() {
+        let mut smpl = vec![0u8; 36];
+        smpl[28..32].copy_from_slice(&2u32.to_le_bytes());
+        // A ping-pong loop first, then the forward loop that should win.
+        for (kind, start, end) in [(1u32, 10u32, 20u32), (0, 1_000, 4_999)] {
+            for field in [0, kind, start, end, 0, 0] {
+                smpl.extend_from_slice(&field.to_le_bytes());
+            }
+        }
+
+        let mut body = b"WAVE".to_vec();
+        body.extend(chunk(b"fmt ", &[0; 16]));
+        body.extend(chunk(b"LIST", &[1, 2, 3]));
+        body.extend(chunk(b"smpl", &smpl));
+        let file = chunk(b"RIFF", &body);
+
+        assert_eq!(smpl_loop(&mut Cursor::new(file)), Some((1_000, 5_000)));
+        assert_eq!(smpl_loop(&mut Cursor::new(b"fLaC....".to_vec())), None);
+
+        // A truncated chunk claiming nearly 4 GiB is read as far as it goes.
+        let mut corrupt = b"RIFF\0\0\0\0WAVEsmpl".to_vec();
+        corrupt.extend_from_slice(&u32::MAX.to_le_bytes());
+        corrupt.extend_from_slice(&smpl[..40]);
+        assert_eq!(smpl_loop(&mut Cursor::new(corrupt)), None);
+    }
+
+    #[test]
+    fn test_decode_reads_loop_from_wav() {
+        let mut fmt = Vec::new();
+        fmt.extend_from_slice(&1u16.to_le_bytes());
+        fmt.extend_from_slice(&1u16.to_le_bytes());
+        fmt.extend_from_slice(&48_000u32.to_le_bytes());
+        fmt.extend_from_slice(&96_000u32.to_le_bytes());
+        fmt.extend_from_slice(&2u16.to_le_bytes());
+        fmt.extend_from_slice(&16u16.to_le_bytes());
+        let mut smpl = vec![0u8; 36];
+        smpl[28..32].copy_from_slice(&1u32.to_le_bytes());
+        for field in [0u32, 0, 10, 99, 0, 0] {
+            smpl.extend_from_slice(&field.to_le_bytes());
+        }
+
+        let mut body = b"WAVE".to_vec();
+        body.extend(chunk(b"fmt ", &fmt));
+        body.extend(chunk(b"data", &[0x40; 200]));
+        body.extend(chunk(b"smpl", &smpl));
+        let path = std::env::temp_dir().join(format!("rakund-{}.wav", uuid::Uuid::new_v4()));
+        std::fs::write(&path, chunk(b"RIFF", &body)).unwrap();
+
+        let sample = decode(&path.to_string_lossy());
+        std::fs::remove_file(&path).ok();
+        let sample = sample.unwrap();
+        assert_eq!(sample.frames(), 100);
+        let region = sample.loop_region.unwrap();
+        assert_eq!((region.start, region.end), (10, 100));
+    }
 }
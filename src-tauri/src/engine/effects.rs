@@ -0,0 +1,302 @@
+use crate::engine::impulse;
+use crate::extra::sketch::instrument::settings::Settings;
+use fundsp::prelude32::{
+    afollow, bell_hz, chorus, convolve, db_amp, delay, highshelf_hz, lowshelf_hz, map, multipass,
+    pass, reverb_stereo, An, AudioNode, AudioUnit, BufferVec, Fade, Frame, Net, NetBackend, NodeId,
+    Wave, U1, U2, U3,
+};
+use fundsp::MAX_BUFFER_SIZE;
+use serde::{Deserialize, Serialize};
+
+const EQ_LOW_HZ: f32 = 200.0;
+
+const EQ_MID_HZ: f32 = 1_000.0;
+
+const EQ_HIGH_HZ: f32 = 4_000.0;
+
+const EQ_Q: f32 = 0.7;
+
+const COMPRESSOR_ATTACK: f32 = 0.005;
+
+const COMPRESSOR_RELEASE: f32 = 0.1;
+
+const CROSSFADE_SECS: f32 = 0.1;
+
+// Master bus settings. Every stage is skipped while it is neutral, so the
+// default chain passes the mix through untouched.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MasterParams {
+    pub eq_low_db: f32,
+    pub eq_mid_db: f32,
+    pub eq_high_db: f32,
+    pub compressor_threshold_db: f32,
+    pub compressor_ratio: f32,
+    pub chorus_mix: f32,
+    pub reverb_mix: f32,
+    pub reverb_room: f32,
+    pub reverb_time: f32,
+    pub reverb_damping: f32,
+    // File name of an impulse response in the irs folder.
+    pub convolution_ir: Option<String>,
+    pub convolution_mix: f32,
+    pub convolution_predelay_ms: f32,
+}
+
+impl Default for MasterParams {
+    fn default() -> Self {
+        Self {
+            eq_low_db: 0.0,
+            eq_mid_db: 0.0,
+            eq_high_db: 0.0,
+            compressor_threshold_db: -12.0,
+            compressor_ratio: 1.0,
+            chorus_mix: 0.0,
+            reverb_mix: 0.0,
+            reverb_room: 20.0,
+            reverb_time: 2.0,
+            reverb_damping: 0.5,
+            convolution_ir: None,
+            convolution_mix: 0.25,
+            convolution_predelay_ms: 0.0,
+        }
+    }
+}
+
+impl MasterParams {
+    pub fn from_settings(settings: &Settings) -> Self {
+        settings
+            .values
+            .get("master_effects")
+            .and_then(|v| serde_json::from_value(v.clone()).ok())
+            .unwrap_or_default()
+    }
+
+    fn has_eq(&self) -> bool {
+        self.eq_low_db != 0.0 || self.eq_mid_db != 0.0 || self.eq_high_db != 0.0
+    }
+}
+
+fn eq_channel(params: &MasterParams) -> An<impl AudioNode<Inputs = U1, Outputs = U1>> {
+    lowshelf_hz(EQ_LOW_HZ, EQ_Q, db_amp(params.eq_low_db))
+        >> bell_hz(EQ_MID_HZ, EQ_Q, db_amp(params.eq_mid_db))
+        >> highshelf_hz(EQ_HIGH_HZ, EQ_Q, db_amp(params.eq_high_db))
+}
+
+// Gain reduction for a detected level above the threshold.
+fn compressor_gain(level: f32, threshold: f32, ratio: f32) -> f32 {
+    if level <= threshold {
+        1.0
+    } else {
+        (level / threshold).powf(1.0 / ratio - 1.0)
+    }
+}
+
+// Wet/dry blend of a stereo response, each side behind its own pre-delay.
+fn convolution(response: &Wave, mix: f32, predelay_secs: f32) -> Net {
+    let mix = mix.clamp(0.0, 1.0);
+    let predelay = predelay_secs.max(0.0);
+    let wet =
+        (delay(predelay) >> convolve(response, 0)) | (delay(predelay) >> convolve(response, 1));
+
+    let mut net = Net::new(2, 2);
+    net.chain(Box::new((multipass::<U2>() * (1.0 - mix)) & (wet * mix)));
+    net
+}
+
+// Builds the stereo master chain: EQ, compressor, chorus, convolution,
+// reverb. An impulse response that cannot be loaded drops its stage.
+pub fn build_chain(params: &MasterParams, sample_rate: u32) -> Net {
+    let mut net = Net::new(2, 2);
+
+    if params.has_eq() {
+        net.chain(Box::new(eq_channel(params) | eq_channel(params)));
+    }
+
+    if params.compressor_ratio > 1.0 {
+        let threshold = db_amp(params.compressor_threshold_db);
+        let ratio = params.compressor_ratio;
+        let detector = map(|f: &Frame<f32, U2>| f[0].abs().max(f[1].abs()))
+            >> afollow(COMPRESSOR_ATTACK, COMPRESSOR_RELEASE)
+            >> map(move |f: &Frame<f32, U1>| compressor_gain(f[0], threshold, ratio));
+        let compressor =
+            (multipass::<U2>() ^ detector) >> map(|f: &Frame<f32, U3>| (f[0] * f[2], f[1] * f[2]));
+        net.chain(Box::new(compressor));
+    }
+
+    if params.chorus_mix > 0.0 {
+        let mix = params.chorus_mix.clamp(0.0, 1.0);
+        let voice = |seed| (pass() * (1.0 - mix)) & (chorus(seed, 0.015, 0.005, 0.3) * mix);
+        net.chain(Box::new(voice(0) | voice(1)));
+    }
+
+    if let Some(name) = params
+        .convolution_ir
+        .as_deref()
+        .filter(|_| params.convolution_mix > 0.0)
+    {
+        match impulse::load(name) {
+            Ok(sample) => {
+                let response = impulse::response(&sample, sample_rate);
+                let predelay = params.convolution_predelay_ms / 1000.0;
+                net.chain(Box::new(convolution(
+                    &response,
+                    params.convolution_mix,
+                    predelay,
+                )));
+            }
+            Err(e) => eprintln!("[EFFECTS] Skipping impulse response {}: {}", name, e),
+        }
+    }
+
+    if params.reverb_mix > 0.0 {
+        let mix = params.reverb_mix.clamp(0.0, 1.0);
+        let reverb = reverb_stereo(
+            params.reverb_room.clamp(1.0, 100.0),
+            params.reverb_time.max(0.1),
+            params.reverb_damping.clamp(0.0, 1.0),
+        );
+        net.chain(Box::new(multipass::<U2>() & (reverb * mix)));
+    }
+
+    if net.size() == 0 {
+        net.chain(Box::new(multipass::<U2>()));
+    }
+    net.set_sample_rate(sample_rate as f64);
+    net
+}
+
+// Control side of the live master bus. Edits are crossfaded in and handed to
+// the audio thread through fundsp's frontend/backend pair, which also takes
+// the replaced chain back for deallocation off the audio thread.
+pub struct MasterBus {
+    net: Net,
+    node: NodeId,
+    sample_rate: u32,
+}
+
+impl MasterBus {
+    pub fn new(sample_rate: u32, params: &MasterParams) -> (Self, NetBackend) {
+        let mut net = Net::new(2, 2);
+        net.set_sample_rate(sample_rate as f64);
+        let node = net.chain(Box::new(build_chain(params, sample_rate)));
+        let backend = net.backend();
+        (
+            Self {
+                net,
+                node,
+                sample_rate,
+            },
+            backend,
+        )
+    }
+
+    pub fn apply(&mut self, params: &MasterParams) {
+        self.net.crossfade(
+            self.node,
+            Fade::Smooth,
+            CROSSFADE_SECS,
+            Box::new(build_chain(params, self.sample_rate)),
+        );
+        self.net.commit();
+    }
+}
+
+// Runs a stereo unit over an interleaved bus. Mono buses are fed to both
+// inputs and folded back; channels past the first two are left dry.
+pub struct EffectsProcessor {
+    unit: Box<dyn AudioUnit>,
+    input: BufferVec,
+    output: BufferVec,
+}
+
+impl EffectsProcessor {
+    pub fn new(mut unit: Box<dyn AudioUnit>) -> Self {
+        unit.allocate();
+        Self {
+            unit,
+            input: BufferVec::new(2),
+            output: BufferVec::new(2),
+        }
+    }
+
+    pub fn process(&mut self, bus: &mut [f32], channels: usize) {
+        for chunk in bus.chunks_mut(MAX_BUFFER_SIZE * channels) {
+            let frames = chunk.len() / channels;
+
+            for (i, frame) in chunk.chunks_exact(channels).enumerate() {
+                self.input.set_f32(0, i, frame[0]);
+                self.input.set_f32(1, i, frame[channels.min(2) - 1]);
+            }
+
+            self.unit.process(
+                frames,
+                &self.input.buffer_ref(),
+                &mut self.output.buffer_mut(),
+            );
+
+            for (i, frame) in chunk.chunks_exact_mut(channels).enumerate() {
+                let left = self.output.at_f32(0, i);
+                let right = self.output.at_f32(1, i);
+                if channels == 1 {
+                    frame[0] = 0.5 * (left + right);
+                } else {
+                    frame[0] = left;
+                    frame[1] = right;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::sample::AudioSample;
+
+    fn impulse_response(params: &MasterParams, frames: usize) -> Vec<f32> {
+        run_impulse(Box::new(build_chain(params, 48_000)), frames)
+    }
+
+    fn run_impulse(unit: Box<dyn AudioUnit>, frames: usize) -> Vec<f32> {
+        let mut fx = EffectsProcessor::new(unit);
+
+        let mut bus = vec![0.0; frames * 2];
+        bus[0] = 1.0;
+        bus[1] = 1.0;
+        fx.process(&mut bus, 2);
+        bus
+    }
+
+    #[test]
+    fn test_neutral_chain_is_transparent() {
+        let out = impulse_response(&MasterParams::default(), 256);
+        assert_eq!(out[0], 1.0);
+        assert!(out[2..].iter().all(|s| *s == 0.0));
+    }
+
+    #[test]
+    fn test_reverb_adds_tail() {
+        let params = MasterParams {
+            reverb_mix: 0.5,
+            ..MasterParams::default()
+        };
+        let out = impulse_response(&params, 48_000);
+        assert!(out[24_000..].iter().any(|s| s.abs() > 1e-4));
+    }
+
+    #[test]
+    fn test_convolution_applies_predelay() {
+        let mut ir = vec![0.0; 4_800];
+        ir[0] = 1.0;
+        let response = impulse::response(&AudioSample::new(ir, 48_000, 1), 48_000);
+
+        let mut stage = convolution(&response, 1.0, 0.01);
+        stage.set_sample_rate(48_000.0);
+        let out = run_impulse(Box::new(stage), 1_024);
+
+        assert!(out[..960].iter().all(|s| s.abs() < 1e-4));
+        assert!((out[960] - 1.0).abs() < 1e-3);
+        assert!((out[961] - 1.0).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,197 @@
+use crate::extra::sketch::instrument::settings::Settings;
+
+const SILENCE: f32 = 0.001;
+
+// Times are in seconds, `sustain` is a level between 0 and 1. Without a
+// release time the voice falls back to the instrument's per-frame
+// `fast_release` multiplier. Released voices whose dampers are lifted decay
+// by the instrument's `slow_release` instead.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EnvelopeParams {
+    pub attack: f32,
+    pub decay: f32,
+    pub sustain: f32,
+    pub release: Option<f32>,
+}
+
+impl Default for EnvelopeParams {
+    fn default() -> Self {
+        Self {
+            attack: 0.0,
+            decay: 0.0,
+            sustain: 1.0,
+            release: None,
+        }
+    }
+}
+
+impl EnvelopeParams {
+    pub fn from_settings(settings: &Settings) -> Self {
+        let defaults = Self::default();
+        Self {
+            attack: settings.attack_time().unwrap_or(defaults.attack).max(0.0),
+            decay: settings.decay_time().unwrap_or(defaults.decay).max(0.0),
+            sustain: settings
+                .sustain_level()
+                .unwrap_or(defaults.sustain)
+                .clamp(0.0, 1.0),
+            release: settings.release_time().filter(|t| *t > 0.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    Done,
+}
+
+#[derive(Debug, Clone)]
+pub struct Envelope {
+    stage: Stage,
+    level: f32,
+    attack_step: f32,
+    decay_step: f32,
+    sustain: f32,
+    release_coeff: f32,
+    held_coeff: f32,
+}
+
+fn seconds_to_frames(secs: f32, sample_rate: u32) -> f32 {
+    (secs * sample_rate as f32).max(1.0)
+}
+
+impl Envelope {
+    // `fast` and `slow` are the instrument's legacy per-frame release
+    // multipliers for damped and undamped strings.
+    pub fn new(params: EnvelopeParams, sample_rate: u32, fast: f32, slow: f32) -> Self {
+        let release_coeff = match params.release {
+            Some(secs) => SILENCE.powf(1.0 / seconds_to_frames(secs, sample_rate)),
+            None => fast,
+        };
+
+        let mut env = Self {
+            stage: Stage::Attack,
+            level: 0.0,
+            attack_step: 1.0 / seconds_to_frames(params.attack, sample_rate),
+            decay_step: (1.0 - params.sustain) / seconds_to_frames(params.decay, sample_rate),
+            sustain: params.sustain,
+            release_coeff,
+            held_coeff: slow.clamp(0.0, 1.0),
+        };
+        if params.attack <= 0.0 {
+            env.level = 1.0;
+            env.stage = Stage::Decay;
+        }
+        env
+    }
+
+    pub fn stage(&self) -> Stage {
+        self.stage
+    }
+
+    pub fn level(&self) -> f32 {
+        self.level
+    }
+
+    pub fn is_releasing(&self) -> bool {
+        matches!(self.stage, Stage::Release | Stage::Done)
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.stage == Stage::Done
+    }
+
+    pub fn release(&mut self) {
+        if self.stage != Stage::Done {
+            self.stage = Stage::Release;
+        }
+    }
+
+    // `damping` blends the release: 1.0 decays at the full release rate,
+    // 0.0 at the slow rate of an undamped string, as a sustain pedal does
+    // for lifted keys.
+    pub fn next(&mut self, damping: f32) -> f32 {
+        match self.stage {
+            Stage::Attack => {
+                self.level += self.attack_step;
+                if self.level >= 1.0 {
+                    self.level = 1.0;
+                    self.stage = Stage::Decay;
+                }
+            }
+            Stage::Decay => {
+                self.level -= self.decay_step;
+                if self.level <= self.sustain {
+                    self.level = self.sustain;
+                    self.stage = Stage::Sustain;
+                }
+            }
+            Stage::Sustain => {}
+            Stage::Release => {
+                let held = (1.0 - self.held_coeff) * (1.0 - damping);
+                self.level *= 1.0 - (1.0 - self.release_coeff) * damping - held;
+                if self.level <= SILENCE {
+                    self.level = 0.0;
+                    self.stage = Stage::Done;
+                }
+            }
+            Stage::Done => {}
+        }
+        self.level
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_envelope_stages() {
+        let params = EnvelopeParams {
+            attack: 0.01,
+            decay: 0.01,
+            sustain: 0.5,
+            release: Some(0.01),
+        };
+        let mut env = Envelope::new(params, 1000, 0.9998, 1.0);
+
+        assert_eq!(env.stage(), Stage::Attack);
+        let first = env.next(1.0);
+        assert!(first > 0.0 && first < 1.0);
+
+        for _ in 0..30 {
+            env.next(1.0);
+        }
+        assert_eq!(env.stage(), Stage::Sustain);
+        assert_eq!(env.level(), 0.5);
+
+        env.release();
+        env.next(0.0);
+        assert_eq!(env.level(), 0.5);
+
+        for _ in 0..20 {
+            env.next(1.0);
+        }
+        assert!(env.is_done());
+        assert_eq!(env.level(), 0.0);
+    }
+
+    #[test]
+    fn test_lifted_damper_decays_at_slow_rate() {
+        let mut env = Envelope::new(EnvelopeParams::default(), 48_000, 0.9, 0.99);
+        env.release();
+        assert!((env.next(0.0) - 0.99).abs() < 1e-6);
+        assert!((env.next(1.0) - 0.99 * 0.9).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_instant_attack_starts_at_full_level() {
+        let env = Envelope::new(EnvelopeParams::default(), 48_000, 0.9998, 1.0);
+        assert_eq!(env.level(), 1.0);
+        assert!(!env.is_releasing());
+    }
+}
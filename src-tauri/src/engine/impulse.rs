@@ -0,0 +1,134 @@
+use crate::engine::decoder;
+use crate::engine::sample::AudioSample;
+use crate::error::{AudioError, Result};
+use crate::state;
+use fundsp::prelude32::Wave;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+// Longer responses are cut off; the convolver's cost grows with the length.
+const MAX_IR_SECS: f32 = 6.0;
+
+const IR_EXTENSIONS: [&str; 2] = ["wav", "flac"];
+
+lazy_static! {
+    static ref IR_CACHE: Mutex<HashMap<String, Arc<AudioSample>>> = Mutex::new(HashMap::new());
+}
+
+fn is_impulse_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| IR_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+}
+
+pub fn list() -> Result<Vec<String>> {
+    let dir = state::irs_dir()?;
+    let entries = fs::read_dir(&dir)
+        .map_err(|e| AudioError::InstrumentError(format!("Cannot read irs dir: {}", e)))?;
+
+    let mut names: Vec<String> = entries
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.is_file() && is_impulse_file(p))
+        .filter_map(|p| p.file_name().map(|n| n.to_string_lossy().into_owned()))
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+// `name` is a file inside the irs folder; decoded responses are kept so
+// tweaking the other effect parameters does not hit the disk again.
+pub fn load(name: &str) -> Result<Arc<AudioSample>> {
+    if let Some(cached) = IR_CACHE.lock().unwrap().get(name) {
+        return Ok(cached.clone());
+    }
+
+    if Path::new(name).file_name().and_then(|n| n.to_str()) != Some(name) {
+        return Err(AudioError::InstrumentError(format!(
+            "Invalid impulse response name: {}",
+            name
+        )));
+    }
+
+    let path = state::irs_dir()?.join(name);
+    let sample = decoder::decode(&path.to_string_lossy())?;
+    IR_CACHE
+        .lock()
+        .unwrap()
+        .insert(name.to_string(), sample.clone());
+    Ok(sample)
+}
+
+// How long a response rings once `response` has trimmed it.
+pub fn length_secs(sample: &AudioSample) -> f32 {
+    sample.duration_secs().min(MAX_IR_SECS)
+}
+
+// Turns a decoded response into a stereo wave at the stream rate, trimmed to
+// MAX_IR_SECS and normalised to unit energy so switching halls does not jump
+// in level. Mono responses feed both sides.
+pub fn response(sample: &AudioSample, sample_rate: u32) -> Wave {
+    let ratio = sample.rate_ratio(sample_rate);
+    let max_frames = (MAX_IR_SECS * sample_rate as f32) as usize;
+    let frames = ((sample.frames() as f32 / ratio) as usize).clamp(1, max_frames.max(1));
+
+    let channels: Vec<Vec<f32>> = (0..sample.channels.min(2))
+        .map(|ch| {
+            (0..frames)
+                .map(|i| {
+                    let pos = i as f32 * ratio;
+                    let idx = pos as usize;
+                    let frac = pos - idx as f32;
+                    let at = |n: usize| {
+                        sample
+                            .data
+                            .get(n * sample.channels + ch)
+                            .copied()
+                            .unwrap_or(0.0)
+                    };
+                    at(idx) * (1.0 - frac) + at(idx + 1) * frac
+                })
+                .collect()
+        })
+        .collect();
+
+    let energy = channels
+        .iter()
+        .map(|c| c.iter().map(|s| s * s).sum::<f32>())
+        .fold(0.0f32, f32::max);
+    let gain = if energy > 0.0 {
+        energy.sqrt().recip()
+    } else {
+        0.0
+    };
+
+    let mut wave = Wave::new(0, sample_rate as f64);
+    for ch in 0..2 {
+        let source = &channels[ch.min(channels.len() - 1)];
+        let scaled: Vec<f32> = source.iter().map(|s| s * gain).collect();
+        wave.push_channel(&scaled);
+    }
+    wave
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_response_resamples_and_normalises() {
+        let mut data = vec![0.0; 44_100];
+        data[0] = 0.5;
+        let sample = AudioSample::new(data, 44_100, 1);
+
+        let wave = response(&sample, 48_000);
+        assert_eq!(wave.channels(), 2);
+        assert_eq!(wave.len(), 48_000);
+        let energy: f32 = (0..wave.len()).map(|i| wave.at(0, i).powi(2)).sum();
+        assert!((energy - 1.0).abs() < 1e-4);
+        assert_eq!(wave.at(0, 0), wave.at(1, 0));
+    }
+}
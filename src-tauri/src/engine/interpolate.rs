@@ -0,0 +1,227 @@
+use crate::engine::sample::SampleLoop;
+use crate::extra::sketch::instrument::settings::Settings;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+use std::time::Instant;
+
+// Zero crossings of the sinc kernel on each side of the read position.
+const SINC_HALF_TAPS: usize = 8;
+
+// Kernel table entries per source frame of distance.
+const SINC_RESOLUTION: usize = 256;
+
+// Pitching up widens the kernel to filter out what would alias; past this
+// step the width, and the cost, stop growing.
+const SINC_MAX_STRETCH: f32 = 4.0;
+
+// Cost measurements read a second of audio a fifth up, a typical shift for
+// instruments sampled every few keys.
+const MEASURE_STEP: f32 = 1.5;
+
+const MEASURE_FRAMES: usize = 48_000;
+
+lazy_static! {
+    // Blackman-windowed sinc from 0 to SINC_HALF_TAPS, with a trailing zero so
+    // lookups can always read the next entry.
+    static ref SINC_TABLE: Vec<f32> = (0..SINC_HALF_TAPS * SINC_RESOLUTION + 2)
+        .map(|i| {
+            let x = i as f64 / SINC_RESOLUTION as f64;
+            if x >= SINC_HALF_TAPS as f64 {
+                return 0.0;
+            }
+            let sinc = if x == 0.0 { 1.0 } else { (PI * x).sin() / (PI * x) };
+            let t = x / SINC_HALF_TAPS as f64;
+            let window = 0.42 + 0.5 * (PI * t).cos() + 0.08 * (2.0 * PI * t).cos();
+            (sinc * window) as f32
+        })
+        .collect();
+}
+
+// How voices read between source frames when pitch-shifted or resampled.
+// Stored as `"cubic"` in instrument settings and the user's state file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Interpolation {
+    #[default]
+    Linear,
+    Cubic,
+    Sinc,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InterpolationCost {
+    pub mode: Interpolation,
+    // Source frames read per output sample at the measured pitch shift.
+    pub taps: usize,
+    pub ns_per_sample: f32,
+    // Share of one CPU core a single voice takes on a 48 kHz stereo stream.
+    pub voice_load: f32,
+}
+
+// Builds the sinc table ahead of time so the audio thread never does.
+pub fn prepare() {
+    lazy_static::initialize(&SINC_TABLE);
+}
+
+fn sinc_kernel(x: f32) -> f32 {
+    let pos = x.abs() * SINC_RESOLUTION as f32;
+    let idx = pos as usize;
+    if idx >= SINC_HALF_TAPS * SINC_RESOLUTION {
+        return 0.0;
+    }
+    let frac = pos - idx as f32;
+    SINC_TABLE[idx] * (1.0 - frac) + SINC_TABLE[idx + 1] * frac
+}
+
+// Catmull-Rom flavoured Hermite between `y1` and `y2`, `t` from 0 to 1.
+pub fn hermite(y0: f32, y1: f32, y2: f32, y3: f32, t: f32) -> f32 {
+    let c1 = 0.5 * (y2 - y0);
+    let c2 = y0 - 2.5 * y1 + 2.0 * y2 - 0.5 * y3;
+    let c3 = 0.5 * (y3 - y0) + 1.5 * (y1 - y2);
+    ((c3 * t + c2) * t + c1) * t + y1
+}
+
+fn sinc_stretch(step: f32) -> f32 {
+    step.abs().clamp(1.0, SINC_MAX_STRETCH)
+}
+
+impl Interpolation {
+    pub const ALL: [Self; 3] = [Self::Linear, Self::Cubic, Self::Sinc];
+
+    pub fn from_settings(settings: &Settings) -> Self {
+        settings
+            .values
+            .get("interpolation")
+            .and_then(|v| serde_json::from_value(v.clone()).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn taps(self, step: f32) -> usize {
+        match self {
+            Self::Linear => 2,
+            Self::Cubic => 4,
+            Self::Sinc => 2 * (SINC_HALF_TAPS as f32 * sinc_stretch(step)).ceil() as usize,
+        }
+    }
+
+    // Reads channel `ch` of interleaved `data` at fractional frame `pos`.
+    // Frames outside the data read as silence. `step` is the voice's playhead
+    // increment, which sets the sinc cutoff.
+    pub fn read(self, data: &[f32], channels: usize, ch: usize, pos: f32, step: f32) -> f32 {
+        let at = |frame: isize| -> f32 {
+            if frame < 0 {
+                return 0.0;
+            }
+            data.get(frame as usize * channels + ch)
+                .copied()
+                .unwrap_or(0.0)
+        };
+        self.read_frames(at, pos, step)
+    }
+
+    // As `read`, but frames from the loop's end on wrap back to its start,
+    // so reads at the seam see the audio the loop continues into.
+    pub fn read_wrapped(
+        self,
+        data: &[f32],
+        channels: usize,
+        ch: usize,
+        pos: f32,
+        step: f32,
+        region: SampleLoop,
+    ) -> f32 {
+        let (start, end) = (region.start as isize, region.end as isize);
+        let len = region.frames().max(1) as isize;
+        let at = |frame: isize| -> f32 {
+            if frame < 0 {
+                return 0.0;
+            }
+            let frame = if frame >= end {
+                start + (frame - end) % len
+            } else {
+                frame
+            };
+            data.get(frame as usize * channels + ch)
+                .copied()
+                .unwrap_or(0.0)
+        };
+        self.read_frames(at, pos, step)
+    }
+
+    fn read_frames(self, at: impl Fn(isize) -> f32, pos: f32, step: f32) -> f32 {
+        let base = pos.floor();
+        let frac = pos - base;
+        let base = base as isize;
+
+        match self {
+            Self::Linear => at(base) * (1.0 - frac) + at(base + 1) * frac,
+            Self::Cubic => hermite(at(base - 1), at(base), at(base + 1), at(base + 2), frac),
+            Self::Sinc => {
+                let stretch = sinc_stretch(step);
+                let cutoff = 1.0 / stretch;
+                let half = (SINC_HALF_TAPS as f32 * stretch).ceil() as isize;
+                let mut sum = 0.0;
+                for k in (1 - half)..=half {
+                    sum += at(base + k) * sinc_kernel((k as f32 - frac) * cutoff);
+                }
+                sum * cutoff
+            }
+        }
+    }
+
+    // Times this mode on a synthetic signal, so the cost can be shown next to
+    // the choice.
+    pub fn measure(self) -> InterpolationCost {
+        prepare();
+        let data: Vec<f32> = (0..MEASURE_FRAMES)
+            .map(|i| ((i * 7_919) % 2_000) as f32 / 1_000.0 - 1.0)
+            .collect();
+        let reads = (MEASURE_FRAMES as f32 / MEASURE_STEP) as usize;
+
+        let start = Instant::now();
+        let mut acc = 0.0;
+        let mut pos = 0.0;
+        for _ in 0..reads {
+            acc += self.read(&data, 1, 0, pos, MEASURE_STEP);
+            pos += MEASURE_STEP;
+        }
+        std::hint::black_box(acc);
+        let ns_per_sample = start.elapsed().as_nanos() as f32 / reads as f32;
+
+        InterpolationCost {
+            mode: self,
+            taps: self.taps(MEASURE_STEP),
+            ns_per_sample,
+            voice_load: ns_per_sample * 48_000.0 * 2.0 / 1e9,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_modes_hit_source_frames() {
+        let data: Vec<f32> = (0..64).map(|i| (i as f32 * 0.3).sin()).collect();
+        for mode in Interpolation::ALL {
+            for i in [10, 20, 30] {
+                let read = mode.read(&data, 1, 0, i as f32, 1.0);
+                assert!((read - data[i]).abs() < 1e-4, "{:?} at {}", mode, i);
+            }
+        }
+    }
+
+    #[test]
+    fn test_sinc_filters_when_pitching_up() {
+        // Nyquist-rate alternation read at double speed would alias to DC.
+        let data: Vec<f32> = (0..256)
+            .map(|i| if i % 2 == 0 { 1.0 } else { -1.0 })
+            .collect();
+        let linear = Interpolation::Linear.read(&data, 1, 0, 128.0, 2.0);
+        let sinc = Interpolation::Sinc.read(&data, 1, 0, 128.0, 2.0);
+        assert_eq!(linear, 1.0);
+        assert!(sinc.abs() < 0.05);
+    }
+}
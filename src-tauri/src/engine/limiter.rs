@@ -0,0 +1,133 @@
+use crate::engine::interpolate::hermite;
+
+// Highest true peak let through, -1 dBFS.
+const CEILING: f32 = 0.891;
+
+const LOOKAHEAD_SECS: f32 = 0.0015;
+
+const RELEASE_SECS: f32 = 0.08;
+
+// Points between two samples checked for inter-sample overs.
+const TRUE_PEAK_POINTS: [f32; 3] = [0.25, 0.5, 0.75];
+
+// Brickwall limiter on an interleaved bus. The gain needed for each frame's
+// true peak is held over the look-ahead window and box-filtered, so it has
+// fully ramped down by the time that frame leaves the delay line; the output
+// is therefore late by `latency()` frames. Buffers are sized up front and
+// `process` neither locks nor allocates.
+pub struct Limiter {
+    channels: usize,
+    window: usize,
+    // Interleaved delay line, `latency()` frames long.
+    delay: Vec<f32>,
+    delay_pos: usize,
+    // Last four input samples per channel for the true-peak estimate.
+    history: Vec<[f32; 4]>,
+    // Required gains over the hold window and the smoothed gains being
+    // averaged, both ring buffers.
+    required: Vec<f32>,
+    smoothed: Vec<f32>,
+    required_pos: usize,
+    smoothed_pos: usize,
+    smoothed_sum: f64,
+    envelope: f32,
+    release: f32,
+}
+
+impl Limiter {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        let channels = channels.max(1);
+        let window = ((LOOKAHEAD_SECS * sample_rate as f32) as usize).max(1);
+        let latency = window + 1;
+        Self {
+            channels,
+            window,
+            delay: vec![0.0; latency * channels],
+            delay_pos: 0,
+            history: vec![[0.0; 4]; channels],
+            required: vec![1.0; window + 2],
+            smoothed: vec![1.0; window],
+            required_pos: 0,
+            smoothed_pos: 0,
+            smoothed_sum: window as f64,
+            envelope: 1.0,
+            release: (-1.0 / (RELEASE_SECS * sample_rate as f32)).exp(),
+        }
+    }
+
+    pub fn latency(&self) -> usize {
+        self.window + 1
+    }
+
+    // Peak of the newest sample and of the curve between the two before it.
+    fn true_peak(&mut self, frame: &[f32]) -> f32 {
+        let mut peak = 0.0f32;
+        for (history, sample) in self.history.iter_mut().zip(frame) {
+            history.rotate_left(1);
+            history[3] = *sample;
+            let [y0, y1, y2, y3] = *history;
+            peak = peak.max(y3.abs());
+            for t in TRUE_PEAK_POINTS {
+                peak = peak.max(hermite(y0, y1, y2, y3, t).abs());
+            }
+        }
+        peak
+    }
+
+    fn next_gain(&mut self, peak: f32) -> f32 {
+        let required = if peak > CEILING { CEILING / peak } else { 1.0 };
+        self.required[self.required_pos] = required;
+        self.required_pos = (self.required_pos + 1) % self.required.len();
+
+        let held = self.required.iter().copied().fold(1.0f32, f32::min);
+        self.envelope = if held < self.envelope {
+            held
+        } else {
+            held + (self.envelope - held) * self.release
+        };
+
+        self.smoothed_sum += (self.envelope - self.smoothed[self.smoothed_pos]) as f64;
+        self.smoothed[self.smoothed_pos] = self.envelope;
+        self.smoothed_pos = (self.smoothed_pos + 1) % self.window;
+        (self.smoothed_sum / self.window as f64) as f32
+    }
+
+    pub fn process(&mut self, bus: &mut [f32]) {
+        let channels = self.channels;
+        for frame in bus.chunks_exact_mut(channels) {
+            let peak = self.true_peak(frame);
+            let gain = self.next_gain(peak);
+
+            let slot = &mut self.delay[self.delay_pos..self.delay_pos + channels];
+            for (out, delayed) in frame.iter_mut().zip(slot.iter_mut()) {
+                let input = *out;
+                *out = (*delayed * gain).clamp(-1.0, 1.0);
+                *delayed = input;
+            }
+            self.delay_pos = (self.delay_pos + channels) % self.delay.len();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limiter_holds_ceiling_and_passes_quiet_audio() {
+        let mut limiter = Limiter::new(48_000, 1);
+        let latency = limiter.latency();
+
+        let mut quiet: Vec<f32> = (0..1_000).map(|i| (i as f32 * 0.05).sin() * 0.5).collect();
+        let input = quiet.clone();
+        limiter.process(&mut quiet);
+        for (out, expected) in quiet[latency..].iter().zip(&input) {
+            assert!((out - expected).abs() < 1e-5);
+        }
+
+        let mut loud: Vec<f32> = (0..4_800).map(|i| (i as f32 * 0.05).sin() * 4.0).collect();
+        limiter.process(&mut loud);
+        assert!(loud.iter().all(|s| s.abs() <= CEILING + 1e-3));
+        assert!(loud.iter().any(|s| s.abs() > 0.8));
+    }
+}
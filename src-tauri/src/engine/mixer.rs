@@ -0,0 +1,1424 @@
+use crate::engine::clock::EngineClock;
+use crate::engine::controller::{ModTarget, Modulation};
+use crate::engine::effects::EffectsProcessor;
+use crate::engine::envelope::{Envelope, EnvelopeParams};
+use crate::engine::interpolate::{self, Interpolation};
+use crate::engine::limiter::Limiter;
+use crate::engine::pedal;
+use crate::engine::resonance::Resonance;
+use crate::engine::sample::{AudioSample, SampleLoop};
+use crate::engine::stereo::{self, StereoImage};
+use crate::engine::tuning::MIN_RATIO;
+use crate::extra::sketch::instrument::release;
+use fundsp::prelude32::AudioUnit;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::SyncSender;
+use std::sync::Arc;
+
+pub const DEFAULT_POLYPHONY: usize = 64;
+
+pub const MAX_POLYPHONY: usize = 256;
+
+// Extra slots for stolen voices to fade out in while their replacements play.
+const STEAL_SLOTS: usize = 32;
+
+const STEAL_FADE_SECS: f32 = 0.005;
+
+// Commands held for a future frame; past this many, new ones apply at once.
+const MAX_PENDING: usize = 1024;
+
+pub const MAX_BLOCK_FRAMES: usize = 1024;
+
+// Mic positions one note can be mixed from.
+pub const MAX_MICS: usize = 4;
+
+// Voices one strike can take: every mic position, each with a blend layer.
+const VOICES_PER_STRIKE: usize = MAX_MICS * 2;
+
+// Fixed gain from the voice sum to the master bus, -9 dB. Dense passages are
+// left to the limiter rather than turning every note down.
+const HEADROOM: f32 = 0.35;
+
+const VOLUME_SMOOTHING_SECS: f32 = 0.02;
+
+#[derive(Debug)]
+pub enum AudioCommand {
+    PlayNote {
+        midi: u8,
+        velocity: u8,
+        sample: Arc<AudioSample>,
+        pitch_ratio: f32,
+        envelope: EnvelopeParams,
+        // A second velocity layer and its share of the note, for crossfading.
+        blend: Option<(Arc<AudioSample>, f32)>,
+        // Mic slot `sample` and `blend` were recorded from, and the same
+        // note from the other loaded mic positions.
+        mic: usize,
+        mics: [Option<MicTake>; MAX_MICS - 1],
+    },
+    // `release` is the key's release-trigger sample, if the instrument has one.
+    StopNote {
+        midi: u8,
+        release: Option<ReleaseTrigger>,
+    },
+    // A sample played to its end regardless of keys or pedals, such as
+    // pedal mechanism noise.
+    OneShot {
+        sample: Arc<AudioSample>,
+        volume: f32,
+    },
+    Sustain {
+        value: u8,
+    },
+    Sostenuto {
+        value: u8,
+    },
+    Soft {
+        value: u8,
+    },
+    Interpolation {
+        mode: Interpolation,
+    },
+    // Linear master volume, 0 to 1, ramped in to avoid zipper noise.
+    Volume {
+        value: f32,
+    },
+    VoiceLimit {
+        polyphony: usize,
+        policy: StealPolicy,
+    },
+    // Bend applied to every voice, already scaled by the bend range.
+    PitchBend {
+        semitones: f32,
+    },
+    ModWheel {
+        value: u8,
+    },
+    ModTarget {
+        target: ModTarget,
+    },
+    // Sympathetic resonance level, 0 to disable, and the first key without
+    // a damper.
+    Resonance {
+        amount: f32,
+        undamped_from: Option<u8>,
+    },
+    StereoImage {
+        image: StereoImage,
+    },
+    // Linear level of each mic slot.
+    MicLevels {
+        levels: [f32; MAX_MICS],
+    },
+    // Pitch ratio for fine tune and reference pitch, 1.0 at A440.
+    Tuning {
+        ratio: f32,
+    },
+    // Drops every command still waiting for its frame, such as song notes
+    // sent ahead before playback was paused or moved.
+    ClearPending,
+}
+
+// One mic position's recording of a note, and of its blend layer.
+#[derive(Debug)]
+pub struct MicTake {
+    pub slot: usize,
+    pub sample: Arc<AudioSample>,
+    pub blend: Option<Arc<AudioSample>>,
+}
+
+// A command and the engine frame it takes effect on. Frames already
+// rendered mean "as soon as possible".
+#[derive(Debug)]
+pub struct ScheduledCommand {
+    pub frame: u64,
+    pub command: AudioCommand,
+}
+
+// The noise of a key's damper returning. Its level scales with the note's
+// velocity and drops by `decay_db_per_sec` for every second the key was held,
+// as the string it damps has died away by then.
+#[derive(Debug)]
+pub struct ReleaseTrigger {
+    pub sample: Arc<AudioSample>,
+    pub pitch_ratio: f32,
+    pub gain: f32,
+    pub decay_db_per_sec: f32,
+}
+
+// Which voice makes room once polyphony is used up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StealPolicy {
+    // Releasing voices first, then the oldest.
+    #[default]
+    Oldest,
+    Quietest,
+    // An earlier strike of the same key, then as `Oldest`.
+    SameNoteFirst,
+}
+
+impl StealPolicy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "oldest" => Some(Self::Oldest),
+            "quietest" => Some(Self::Quietest),
+            "same_note_first" => Some(Self::SameNoteFirst),
+            _ => None,
+        }
+    }
+}
+
+// Counters the mixer publishes for the control side to read.
+#[derive(Debug, Default)]
+pub struct VoiceStats {
+    active: AtomicUsize,
+    peak: AtomicUsize,
+    stolen: AtomicU64,
+}
+
+impl VoiceStats {
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    pub fn peak(&self) -> usize {
+        self.peak.load(Ordering::Relaxed)
+    }
+
+    pub fn stolen(&self) -> u64 {
+        self.stolen.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Clone)]
+pub struct Voice {
+    pub sample: Arc<AudioSample>,
+    pub playhead: f32,
+    pub pitch_ratio: f32,
+    pub step: f32,
+    pub channels: usize,
+    pub midi_note: u8,
+    pub volume: f32,
+    pub envelope: Envelope,
+    pub pedal_hold: bool,
+    pub sostenuto_hold: bool,
+    pub serial: u64,
+    // Gain of a stolen voice on its way out; 1.0 while it plays normally.
+    pub fade: f32,
+    pub stolen: bool,
+    pub velocity: u8,
+    // Engine frame the voice started on.
+    pub started: u64,
+    // Release and pedal noises ignore keys and pedals and play to their end.
+    pub one_shot: bool,
+    // Position of a mono source on a stereo bus, -1 to 1.
+    pub pan: f32,
+    pub mic: usize,
+    // Other mic positions and blend layers of a strike share its serial and
+    // follow it: only the strike counts against polyphony and is stolen.
+    pub companion: bool,
+    // Cleared when a key without a damper comes up, so a looped sample
+    // plays out its tail instead of ringing forever.
+    pub looping: bool,
+}
+
+// Voice mixing shared by the realtime stream and the offline renderer.
+// Nothing in `handle` or `render` locks or allocates: voices live in a fixed
+// pool and the mix bus is sized up front, so both are safe to call from the
+// device callback.
+pub struct Mixer {
+    sample_rate: u32,
+    channels: usize,
+    damping: f32,
+    sostenuto: bool,
+    soft: f32,
+    interpolation: Interpolation,
+    polyphony: usize,
+    steal_policy: StealPolicy,
+    steal_fade_step: f32,
+    stats: Arc<VoiceStats>,
+    clock: u64,
+    engine_clock: Option<Arc<EngineClock>>,
+    pending: Vec<ScheduledCommand>,
+    modulation: Modulation,
+    resonance: Resonance,
+    // Keys from here up have no dampers and ignore note-offs.
+    undamped_from: u8,
+    image: StereoImage,
+    mic_levels: [f32; MAX_MICS],
+    tuning: f32,
+    voices: Vec<Option<Voice>>,
+    // Release triggers per key waiting for the dampers to come down.
+    deferred: Vec<Option<ReleaseTrigger>>,
+    next_serial: u64,
+    mix: Vec<f32>,
+    retired: Option<SyncSender<Arc<AudioSample>>>,
+    effects: Option<EffectsProcessor>,
+    volume: f32,
+    volume_target: f32,
+    volume_smoothing: f32,
+    limiter: Limiter,
+}
+
+// Hands a finished voice's sample to a collector thread so the last
+// reference is never released, and the buffer freed, on the audio thread.
+fn retire(retired: &Option<SyncSender<Arc<AudioSample>>>, slot: &mut Option<Voice>) {
+    if let Some(voice) = slot.take() {
+        retire_sample(retired, voice.sample);
+    }
+}
+
+fn retire_sample(retired: &Option<SyncSender<Arc<AudioSample>>>, sample: Arc<AudioSample>) {
+    if let Some(tx) = retired {
+        let _ = tx.try_send(sample);
+    }
+}
+
+// Retires the samples a dropped command holds.
+fn retire_command(retired: &Option<SyncSender<Arc<AudioSample>>>, cmd: AudioCommand) {
+    match cmd {
+        AudioCommand::PlayNote {
+            sample,
+            blend,
+            mics,
+            ..
+        } => {
+            retire_sample(retired, sample);
+            if let Some((sample, _)) = blend {
+                retire_sample(retired, sample);
+            }
+            for take in mics.into_iter().flatten() {
+                retire_sample(retired, take.sample);
+                if let Some(sample) = take.blend {
+                    retire_sample(retired, sample);
+                }
+            }
+        }
+        AudioCommand::StopNote {
+            release: Some(trigger),
+            ..
+        } => retire_sample(retired, trigger.sample),
+        AudioCommand::OneShot { sample, .. } => retire_sample(retired, sample),
+        _ => {}
+    }
+}
+
+// Reads a voice's sample, blending the last `crossfade` frames of a loop
+// into the frames just before its start so the wrap lands on matching audio.
+fn read_looped(
+    interpolation: Interpolation,
+    data: &[f32],
+    channels: usize,
+    ch: usize,
+    pos: f32,
+    step: f32,
+    region: Option<SampleLoop>,
+) -> f32 {
+    let Some(region) = region else {
+        return interpolation.read(data, channels, ch, pos, step);
+    };
+    let sample = interpolation.read_wrapped(data, channels, ch, pos, step, region);
+    if region.crossfade == 0 {
+        return sample;
+    }
+    let fade_start = (region.end - region.crossfade) as f32;
+    if pos < fade_start {
+        return sample;
+    }
+    let t = ((pos - fade_start) / region.crossfade as f32).min(1.0);
+    let early = interpolation.read(data, channels, ch, pos - region.frames() as f32, step);
+    sample * (1.0 - t) + early * t
+}
+
+impl Mixer {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        let channels = channels.max(1);
+        interpolate::prepare();
+        Self {
+            sample_rate,
+            channels,
+            damping: 1.0,
+            sostenuto: false,
+            soft: 0.0,
+            interpolation: Interpolation::default(),
+            polyphony: DEFAULT_POLYPHONY,
+            steal_policy: StealPolicy::default(),
+            steal_fade_step: 1.0 / (STEAL_FADE_SECS * sample_rate as f32).max(1.0),
+            stats: Arc::new(VoiceStats::default()),
+            clock: 0,
+            engine_clock: None,
+            pending: Vec::with_capacity(MAX_PENDING),
+            modulation: Modulation::new(sample_rate, channels),
+            resonance: Resonance::new(sample_rate),
+            undamped_from: u8::MAX,
+            image: StereoImage::default(),
+            mic_levels: [1.0; MAX_MICS],
+            tuning: 1.0,
+            voices: (0..(MAX_POLYPHONY + STEAL_SLOTS) * VOICES_PER_STRIKE)
+                .map(|_| None)
+                .collect(),
+            deferred: (0..128).map(|_| None).collect(),
+            next_serial: 0,
+            mix: vec![0.0; MAX_BLOCK_FRAMES * channels],
+            retired: None,
+            effects: None,
+            volume: 1.0,
+            volume_target: 1.0,
+            volume_smoothing: (-1.0 / (VOLUME_SMOOTHING_SECS * sample_rate as f32)).exp(),
+            limiter: Limiter::new(sample_rate, channels),
+        }
+    }
+
+    pub fn set_retire_queue(&mut self, tx: SyncSender<Arc<AudioSample>>) {
+        self.retired = Some(tx);
+    }
+
+    // Inserts a stereo unit between the voice mix and the output clipper.
+    pub fn set_effects(&mut self, unit: Box<dyn AudioUnit>) {
+        self.effects = Some(EffectsProcessor::new(unit));
+    }
+
+    pub fn set_interpolation(&mut self, mode: Interpolation) {
+        self.interpolation = mode;
+    }
+
+    pub fn set_voice_limit(&mut self, polyphony: usize, policy: StealPolicy) {
+        self.polyphony = polyphony.clamp(1, MAX_POLYPHONY);
+        self.steal_policy = policy;
+    }
+
+    pub fn set_mod_target(&mut self, target: ModTarget) {
+        self.modulation.set_target(target);
+    }
+
+    pub fn set_resonance(&mut self, amount: f32, undamped_from: Option<u8>) {
+        self.resonance.set_amount(amount);
+        self.undamped_from = undamped_from.unwrap_or(u8::MAX);
+    }
+
+    // Applies to notes started from now on; width applies at once.
+    pub fn set_stereo_image(&mut self, image: StereoImage) {
+        self.image = StereoImage {
+            width: image.width.clamp(0.0, stereo::MAX_WIDTH),
+            ..image
+        };
+    }
+
+    pub fn set_mic_levels(&mut self, levels: [f32; MAX_MICS]) {
+        self.mic_levels = levels.map(|l| l.clamp(0.0, 1.0));
+    }
+
+    pub fn set_tuning(&mut self, ratio: f32) {
+        self.tuning = ratio.max(MIN_RATIO);
+        self.resonance.set_tuning(self.tuning);
+    }
+
+    pub fn set_stats(&mut self, stats: Arc<VoiceStats>) {
+        self.stats = stats;
+    }
+
+    // Publishes this mixer's frame count to `clock`, continuing from wherever
+    // the clock stands so a replacement stream does not jump back in time.
+    pub fn set_clock(&mut self, clock: Arc<EngineClock>) {
+        self.clock = clock.frame();
+        self.engine_clock = Some(clock);
+    }
+
+    // Frames rendered so far.
+    pub fn clock(&self) -> u64 {
+        self.clock
+    }
+
+    pub fn stats(&self) -> &VoiceStats {
+        &self.stats
+    }
+
+    // Jumps straight to `value`; commands ramp instead.
+    pub fn set_volume(&mut self, value: f32) {
+        self.volume_target = value.clamp(0.0, 1.0);
+        self.volume = self.volume_target;
+    }
+
+    // Frames the limiter's look-ahead delays the output by.
+    pub fn latency(&self) -> usize {
+        self.limiter.latency()
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn active_voices(&self) -> usize {
+        self.voices.iter().filter(|v| v.is_some()).count()
+    }
+
+    pub fn damping(&self) -> f32 {
+        self.damping
+    }
+
+    // Strikes counting against polyphony; stolen ones are already on their
+    // way out.
+    fn sounding(&self) -> usize {
+        self.voices
+            .iter()
+            .flatten()
+            .filter(|v| !v.stolen && !v.companion)
+            .count()
+    }
+
+    fn strikes(&self) -> usize {
+        self.voices
+            .iter()
+            .flatten()
+            .filter(|v| !v.companion)
+            .count()
+    }
+
+    fn steal_candidate(&self, midi: u8) -> Option<usize> {
+        let candidates = || {
+            self.voices
+                .iter()
+                .enumerate()
+                .filter_map(|(idx, v)| v.as_ref().map(|v| (idx, v)))
+                .filter(|(_, v)| !v.stolen && !v.companion)
+        };
+        let oldest = || {
+            candidates()
+                .min_by_key(|(_, v)| (!v.envelope.is_releasing(), v.serial))
+                .map(|(idx, _)| idx)
+        };
+
+        match self.steal_policy {
+            StealPolicy::Oldest => oldest(),
+            StealPolicy::Quietest => candidates()
+                .min_by(|(_, a), (_, b)| {
+                    let level = |v: &Voice| v.volume * v.envelope.level();
+                    level(a).total_cmp(&level(b))
+                })
+                .map(|(idx, _)| idx),
+            StealPolicy::SameNoteFirst => candidates()
+                .filter(|(_, v)| v.midi_note == midi)
+                .min_by_key(|(_, v)| v.serial)
+                .map(|(idx, _)| idx)
+                .or_else(oldest),
+        }
+    }
+
+    // Once polyphony is used up, a strike chosen by the steal policy fades
+    // out, with all its companions, over STEAL_FADE_SECS instead of being
+    // cut. Only when every slot is busy fading is the quietest fading voice
+    // dropped outright.
+    fn allocate_slot(&mut self, midi: u8, companion: bool) -> usize {
+        if !companion && self.sounding() >= self.polyphony {
+            let victim = self
+                .steal_candidate(midi)
+                .and_then(|i| self.voices[i].as_ref())
+                .map(|v| v.serial);
+            if let Some(serial) = victim {
+                for v in self.voices.iter_mut().flatten() {
+                    if v.serial == serial {
+                        v.stolen = true;
+                    }
+                }
+                self.stats.stolen.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        if let Some(idx) = self.voices.iter().position(|v| v.is_none()) {
+            return idx;
+        }
+        self.voices
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, v)| v.as_ref().map(|v| (idx, v.fade)))
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(idx, _)| idx)
+            .unwrap_or(0)
+    }
+
+    // Starts a strike, or a companion of the strike `voice.serial` names.
+    // Returns the serial companions should carry.
+    fn start_voice(&mut self, mut voice: Voice) -> u64 {
+        let idx = self.allocate_slot(voice.midi_note, voice.companion);
+        retire(&self.retired, &mut self.voices[idx]);
+
+        voice.step = voice.pitch_ratio * voice.sample.rate_ratio(self.sample_rate);
+        voice.channels = voice.sample.channels;
+        if !voice.companion {
+            voice.serial = self.next_serial;
+            self.next_serial += 1;
+        }
+        voice.started = self.clock;
+        let serial = voice.serial;
+        self.voices[idx] = Some(voice);
+        serial
+    }
+
+    fn start_one_shot(
+        &mut self,
+        sample: Arc<AudioSample>,
+        pitch_ratio: f32,
+        midi: u8,
+        volume: f32,
+        pan: f32,
+    ) {
+        let envelope = Envelope::new(
+            EnvelopeParams::default(),
+            self.sample_rate,
+            release::get_fast(),
+            release::get_slow(),
+        );
+        self.start_voice(Voice {
+            sample,
+            playhead: 0.0,
+            pitch_ratio,
+            step: 0.0,
+            channels: 0,
+            midi_note: midi,
+            volume,
+            envelope,
+            pedal_hold: false,
+            sostenuto_hold: false,
+            serial: 0,
+            fade: 1.0,
+            stolen: false,
+            velocity: 0,
+            started: 0,
+            one_shot: true,
+            pan,
+            mic: 0,
+            companion: false,
+            looping: false,
+        });
+    }
+
+    fn fire_release(&mut self, midi: u8, trigger: ReleaseTrigger) {
+        let pan = self.image.key_pan(midi);
+        self.start_one_shot(trigger.sample, trigger.pitch_ratio, midi, trigger.gain, pan);
+    }
+
+    // Whether the key's damper is still off the strings after it came up.
+    fn damper_lifted(&self, midi: u8) -> bool {
+        self.damping < 1.0
+            || self
+                .voices
+                .iter()
+                .flatten()
+                .any(|v| v.midi_note == midi && !v.one_shot && v.sostenuto_hold)
+    }
+
+    // Fires deferred release triggers whose dampers have come down.
+    fn flush_releases(&mut self) {
+        for midi in 0..self.deferred.len() as u8 {
+            if self.deferred[midi as usize].is_none() || self.damper_lifted(midi) {
+                continue;
+            }
+            if let Some(trigger) = self.deferred[midi as usize].take() {
+                self.fire_release(midi, trigger);
+            }
+        }
+    }
+
+    pub fn handle(&mut self, cmd: AudioCommand) {
+        match cmd {
+            AudioCommand::PlayNote {
+                midi,
+                velocity,
+                sample,
+                pitch_ratio,
+                envelope,
+                blend,
+                mic,
+                mics,
+            } => {
+                // Restriking a key silences its previous voice whatever the
+                // pedal is doing. A damper lifted by sostenuto stays lifted for
+                // the new strike.
+                let mut sostenuto_hold = false;
+                for v in self.voices.iter_mut().flatten() {
+                    if v.midi_note == midi && !v.one_shot {
+                        v.envelope.release();
+                        v.pedal_hold = false;
+                        sostenuto_hold |= v.sostenuto_hold;
+                        v.sostenuto_hold = false;
+                    }
+                }
+                // The new strike lifts the damper again before it lands.
+                if let Some(trigger) = self.deferred[midi as usize & 127].take() {
+                    retire_sample(&self.retired, trigger.sample);
+                }
+
+                let volume = velocity as f32 / 127.0 * pedal::soft_gain(self.soft);
+                let envelope = Envelope::new(
+                    envelope,
+                    self.sample_rate,
+                    release::get_fast(),
+                    release::get_slow(),
+                );
+
+                // Equal-power split so a blended note is as loud as a single
+                // layer at the same velocity.
+                let mix = blend
+                    .as_ref()
+                    .map(|(_, mix)| mix.clamp(0.0, 1.0))
+                    .unwrap_or(0.0);
+                let angle = mix * std::f32::consts::FRAC_PI_2;
+
+                let voice = Voice {
+                    sample,
+                    playhead: 0.0,
+                    pitch_ratio,
+                    step: 0.0,
+                    channels: 0,
+                    midi_note: midi,
+                    volume: volume * angle.cos(),
+                    envelope,
+                    pedal_hold: false,
+                    sostenuto_hold,
+                    serial: 0,
+                    fade: 1.0,
+                    stolen: false,
+                    velocity,
+                    started: 0,
+                    one_shot: false,
+                    pan: self.image.key_pan(midi),
+                    mic: mic.min(MAX_MICS - 1),
+                    companion: false,
+                    looping: true,
+                };
+                let companion = Voice {
+                    serial: self.start_voice(voice.clone()),
+                    companion: true,
+                    ..voice
+                };
+                if let Some((sample, _)) = blend {
+                    self.start_voice(Voice {
+                        sample,
+                        volume: volume * angle.sin(),
+                        ..companion.clone()
+                    });
+                }
+                for take in mics.into_iter().flatten() {
+                    let mic = take.slot.min(MAX_MICS - 1);
+                    if let Some(sample) = take.blend {
+                        self.start_voice(Voice {
+                            sample,
+                            mic,
+                            volume: volume * angle.sin(),
+                            ..companion.clone()
+                        });
+                    }
+                    self.start_voice(Voice {
+                        sample: take.sample,
+                        mic,
+                        ..companion.clone()
+                    });
+                }
+            }
+            AudioCommand::StopNote { midi, release } => {
+                if midi >= self.undamped_from {
+                    // No damper lands, but looped samples stop looping and
+                    // play out their tail. One looped to its last frame has
+                    // no tail, so it fades at the release rate instead.
+                    for v in self.voices.iter_mut().flatten() {
+                        if v.midi_note == midi && !v.one_shot && v.looping {
+                            v.looping = false;
+                            let frames = v.sample.frames();
+                            if v.sample.loop_region.is_some_and(|r| r.end >= frames) {
+                                v.envelope.release();
+                            }
+                        }
+                    }
+                    if let Some(trigger) = release {
+                        retire_sample(&self.retired, trigger.sample);
+                    }
+                    return;
+                }
+
+                // Velocity and start of the key's latest strike still held.
+                let mut struck: Option<(u8, u64)> = None;
+                for v in self.voices.iter_mut().flatten() {
+                    if v.midi_note == midi && !v.one_shot && !v.envelope.is_releasing() {
+                        v.envelope.release();
+                        v.pedal_hold = true;
+                        if struck.is_none_or(|(_, started)| v.started > started) {
+                            struck = Some((v.velocity, v.started));
+                        }
+                    }
+                }
+
+                let Some(mut trigger) = release else {
+                    return;
+                };
+                let Some((velocity, started)) = struck else {
+                    retire_sample(&self.retired, trigger.sample);
+                    return;
+                };
+                let held = self.clock.saturating_sub(started) as f32 / self.sample_rate as f32;
+                let decay_db = trigger.decay_db_per_sec.max(0.0) * held;
+                trigger.gain *= velocity as f32 / 127.0 * 10f32.powf(-decay_db / 20.0);
+
+                // The damper only lands once neither pedal holds it up.
+                if self.damper_lifted(midi) {
+                    if let Some(previous) = self.deferred[midi as usize & 127].replace(trigger) {
+                        retire_sample(&self.retired, previous.sample);
+                    }
+                } else {
+                    self.fire_release(midi, trigger);
+                }
+            }
+            AudioCommand::OneShot { sample, volume } => {
+                self.start_one_shot(sample, 1.0, 0, volume, 0.0);
+            }
+            AudioCommand::Sustain { value } => {
+                self.damping = pedal::damping(value);
+                self.flush_releases();
+            }
+            AudioCommand::Sostenuto { value } => {
+                let pressed = pedal::is_pressed(value);
+                if pressed && !self.sostenuto {
+                    for v in self.voices.iter_mut().flatten() {
+                        if !v.one_shot && !v.envelope.is_releasing() {
+                            v.sostenuto_hold = true;
+                        }
+                    }
+                } else if !pressed {
+                    for v in self.voices.iter_mut().flatten() {
+                        v.sostenuto_hold = false;
+                    }
+                }
+                self.sostenuto = pressed;
+                if !pressed {
+                    self.flush_releases();
+                }
+            }
+            AudioCommand::Soft { value } => {
+                self.soft = pedal::pedal_position(value);
+            }
+            AudioCommand::Interpolation { mode } => {
+                self.interpolation = mode;
+            }
+            AudioCommand::Volume { value } => {
+                self.volume_target = value.clamp(0.0, 1.0);
+            }
+            AudioCommand::VoiceLimit { polyphony, policy } => {
+                self.set_voice_limit(polyphony, policy);
+            }
+            AudioCommand::PitchBend { semitones } => {
+                self.modulation.set_bend(semitones);
+            }
+            AudioCommand::ModWheel { value } => {
+                self.modulation.set_wheel(value);
+            }
+            AudioCommand::ModTarget { target } => {
+                self.modulation.set_target(target);
+            }
+            AudioCommand::Resonance {
+                amount,
+                undamped_from,
+            } => {
+                self.set_resonance(amount, undamped_from);
+            }
+            AudioCommand::StereoImage { image } => {
+                self.set_stereo_image(image);
+            }
+            AudioCommand::MicLevels { levels } => {
+                self.set_mic_levels(levels);
+            }
+            AudioCommand::Tuning { ratio } => {
+                self.set_tuning(ratio);
+            }
+            AudioCommand::ClearPending => {
+                for cmd in self.pending.drain(..) {
+                    retire_command(&self.retired, cmd.command);
+                }
+            }
+        }
+    }
+
+    // Queues `cmd` for its frame, or applies it now if that frame has passed.
+    pub fn schedule(&mut self, cmd: ScheduledCommand) {
+        if cmd.frame <= self.clock || self.pending.len() >= MAX_PENDING {
+            self.handle(cmd.command);
+        } else {
+            self.pending.push(cmd);
+        }
+    }
+
+    // Applies every pending command that is due, in the order they arrived.
+    fn apply_due(&mut self) {
+        while let Some(idx) = self.pending.iter().position(|c| c.frame <= self.clock) {
+            let cmd = self.pending.remove(idx);
+            self.handle(cmd.command);
+        }
+    }
+
+    // Fills `output` with interleaved frames for the mixer's channel count.
+    // Blocks are split at pending command frames so each lands on its exact
+    // frame whatever the buffer size.
+    pub fn render(&mut self, output: &mut [f32]) {
+        if let Some(clock) = &self.engine_clock {
+            clock.publish(self.clock, self.sample_rate);
+        }
+
+        output.fill(0.0);
+        let channels = self.channels;
+        let bus_len = output.len() / channels * channels;
+        let mut start = 0;
+        while start < bus_len {
+            self.apply_due();
+            let remaining = (bus_len - start) / channels;
+            let until_next = self
+                .pending
+                .iter()
+                .map(|c| (c.frame - self.clock) as usize)
+                .min()
+                .unwrap_or(remaining);
+            let frames = remaining.min(until_next).min(MAX_BLOCK_FRAMES);
+
+            let end = start + frames * channels;
+            self.render_block(&mut output[start..end]);
+            self.clock += frames as u64;
+            start = end;
+        }
+    }
+
+    // Lifts the resonance model's dampers for held and sostenuto keys, the
+    // undamped register, and by the sustain pedal's travel for the rest.
+    fn update_dampers(&mut self) {
+        let mut held = [false; 128];
+        for v in self.voices.iter().flatten().filter(|v| !v.one_shot) {
+            if !v.envelope.is_releasing() || v.sostenuto_hold {
+                held[v.midi_note as usize & 127] = true;
+            }
+        }
+        for (midi, held) in held.into_iter().enumerate() {
+            let midi = midi as u8;
+            let freedom = if held || midi >= self.undamped_from {
+                1.0
+            } else {
+                1.0 - self.damping
+            };
+            self.resonance.set_free(midi, freedom);
+        }
+    }
+
+    fn render_block(&mut self, output: &mut [f32]) {
+        let channels = self.channels;
+        let interpolation = self.interpolation;
+        let steal_fade_step = self.steal_fade_step;
+        let width = self.image.width;
+        let mic_levels = self.mic_levels;
+        let tuning = self.tuning;
+        let frames = output.len() / channels;
+        self.modulation.advance(frames);
+        let pitch = self.modulation.pitch(frames);
+
+        let mix = &mut self.mix[..output.len()];
+        mix.fill(0.0);
+
+        for v in self.voices.iter_mut().flatten() {
+            let data = &v.sample.data;
+            let src_channels = v.channels;
+            let src_frames = data.len() / src_channels;
+            let damping = if v.sostenuto_hold {
+                0.0
+            } else if v.pedal_hold {
+                self.damping
+            } else {
+                1.0
+            };
+            // Key panning places mono sources on a stereo bus; stereo
+            // sources get the width control instead.
+            let pan = if channels == 2 && src_channels == 1 {
+                stereo::pan_gains(v.pan)
+            } else {
+                [1.0; 2]
+            };
+            let widen = channels == 2 && src_channels == 2 && width != 1.0;
+            // Looped samples sustain until the envelope ends the voice.
+            let region = v.sample.loop_region.filter(|_| !v.one_shot && v.looping);
+            let read = |ch: usize, pos: f32, step: f32| {
+                read_looped(interpolation, data, src_channels, ch, pos, step, region)
+            };
+
+            for (frame, bend) in mix.chunks_exact_mut(channels).zip(pitch) {
+                let pos = v.playhead as usize;
+                if (region.is_none() && pos + 1 >= src_frames) || v.fade <= 0.0 {
+                    break;
+                }
+                if v.stolen {
+                    v.fade = (v.fade - steal_fade_step).max(0.0);
+                }
+                let gain = v.volume * v.envelope.next(damping) * v.fade * mic_levels[v.mic];
+                let step = v.step * bend * tuning;
+
+                // Mono sources feed every output channel; multi-channel
+                // sources are mapped channel-for-channel and wrap around
+                // on wider buses. Narrower buses average the source
+                // channels that wrap onto each output.
+                if widen {
+                    let left = read(0, v.playhead, step);
+                    let right = read(1, v.playhead, step);
+                    let (left, right) = stereo::widen(left, right, width);
+                    frame[0] += left * gain;
+                    frame[1] += right * gain;
+                } else if channels < src_channels {
+                    for (ch, out) in frame.iter_mut().enumerate() {
+                        let sources = (ch..src_channels).step_by(channels);
+                        let count = sources.len() as f32;
+                        let sum: f32 = sources.map(|src| read(src, v.playhead, step)).sum();
+                        *out += sum / count * gain;
+                    }
+                } else {
+                    for (ch, out) in frame.iter_mut().enumerate() {
+                        let src = ch % src_channels;
+                        let sample = read(src, v.playhead, step);
+                        *out += sample * gain * pan.get(ch).copied().unwrap_or(1.0);
+                    }
+                }
+
+                v.playhead += step;
+                if let Some(region) = region {
+                    if v.playhead >= region.end as f32 {
+                        let over = v.playhead - region.start as f32;
+                        v.playhead = region.start as f32 + over % region.frames() as f32;
+                    }
+                }
+            }
+        }
+
+        for slot in self.voices.iter_mut() {
+            let finished = slot.as_ref().is_some_and(|v| {
+                let looped = v.sample.loop_region.is_some() && !v.one_shot && v.looping;
+                v.envelope.is_done()
+                    || v.fade <= 0.0
+                    || (!looped && (v.playhead as usize + 1) >= v.sample.frames())
+            });
+            if finished {
+                retire(&self.retired, slot);
+            }
+        }
+
+        let active = self.strikes();
+        self.stats.active.store(active, Ordering::Relaxed);
+        self.stats.peak.fetch_max(active, Ordering::Relaxed);
+
+        if self.resonance.is_enabled() {
+            self.update_dampers();
+        }
+
+        let mix = &mut self.mix[..output.len()];
+        self.resonance.process(mix, channels);
+        self.modulation.filter(mix, channels);
+        mix.iter_mut().for_each(|s| *s *= HEADROOM);
+        if let Some(effects) = self.effects.as_mut() {
+            effects.process(mix, channels);
+        }
+
+        for (out, frame) in output
+            .chunks_exact_mut(channels)
+            .zip(mix.chunks_exact(channels))
+        {
+            self.volume =
+                self.volume_target + (self.volume - self.volume_target) * self.volume_smoothing;
+            for (out, s) in out.iter_mut().zip(frame) {
+                *out = s * self.volume;
+            }
+        }
+        self.limiter.process(output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(midi: u8) -> AudioCommand {
+        AudioCommand::PlayNote {
+            midi,
+            velocity: 127,
+            sample: Arc::new(AudioSample::new(vec![0.5; 48_000], 48_000, 1)),
+            pitch_ratio: 1.0,
+            envelope: EnvelopeParams {
+                release: Some(0.01),
+                ..EnvelopeParams::default()
+            },
+            blend: None,
+            mic: 0,
+            mics: Default::default(),
+        }
+    }
+
+    fn with_mic(midi: u8) -> AudioCommand {
+        let AudioCommand::PlayNote {
+            midi,
+            velocity,
+            sample,
+            pitch_ratio,
+            envelope,
+            blend,
+            mic,
+            ..
+        } = play(midi)
+        else {
+            unreachable!()
+        };
+        let mut mics: [Option<MicTake>; MAX_MICS - 1] = Default::default();
+        mics[0] = Some(MicTake {
+            slot: 2,
+            sample: sample.clone(),
+            blend: None,
+        });
+        AudioCommand::PlayNote {
+            midi,
+            velocity,
+            sample,
+            pitch_ratio,
+            envelope,
+            blend,
+            mic,
+            mics,
+        }
+    }
+
+    fn peak(mixer: &mut Mixer, frames: usize) -> f32 {
+        let mut out = vec![0.0; frames * mixer.channels()];
+        mixer.render(&mut out);
+        out.iter().fold(0.0f32, |m, s| m.max(s.abs()))
+    }
+
+    #[test]
+    fn test_sustain_pedal_holds_released_notes() {
+        let mut mixer = Mixer::new(48_000, 1);
+        mixer.handle(AudioCommand::Sustain { value: 127 });
+        mixer.handle(play(60));
+        let held = peak(&mut mixer, 256);
+
+        mixer.handle(AudioCommand::StopNote {
+            midi: 60,
+            release: None,
+        });
+        peak(&mut mixer, 4_800);
+        assert_eq!(peak(&mut mixer, 256), held);
+
+        mixer.handle(AudioCommand::Sustain { value: 0 });
+        peak(&mut mixer, 4_800);
+        assert_eq!(mixer.active_voices(), 0);
+    }
+
+    #[test]
+    fn test_sostenuto_holds_only_keys_down_when_pressed() {
+        let mut mixer = Mixer::new(48_000, 1);
+        mixer.handle(play(60));
+        mixer.handle(AudioCommand::Sostenuto { value: 127 });
+        mixer.handle(play(64));
+        peak(&mut mixer, 256);
+
+        mixer.handle(AudioCommand::StopNote {
+            midi: 60,
+            release: None,
+        });
+        mixer.handle(AudioCommand::StopNote {
+            midi: 64,
+            release: None,
+        });
+        peak(&mut mixer, 4_800);
+        assert_eq!(mixer.active_voices(), 1);
+
+        mixer.handle(AudioCommand::Sostenuto { value: 0 });
+        peak(&mut mixer, 4_800);
+        assert_eq!(mixer.active_voices(), 0);
+    }
+
+    #[test]
+    fn test_scheduled_command_lands_on_its_frame() {
+        let mut mixer = Mixer::new(48_000, 1);
+        let start = 100 + mixer.latency();
+        mixer.schedule(ScheduledCommand {
+            frame: 100,
+            command: play(60),
+        });
+
+        let mut out = vec![0.0; 512];
+        mixer.render(&mut out);
+        assert!(out[..start].iter().all(|s| *s == 0.0));
+        assert!(out[start] > 0.0);
+        assert_eq!(mixer.clock(), 512);
+    }
+
+    #[test]
+    fn test_clear_pending_drops_notes_sent_ahead() {
+        let mut mixer = Mixer::new(48_000, 1);
+        mixer.schedule(ScheduledCommand {
+            frame: 1_000,
+            command: play(60),
+        });
+        mixer.schedule(ScheduledCommand {
+            frame: 0,
+            command: AudioCommand::ClearPending,
+        });
+
+        assert_eq!(peak(&mut mixer, 2_048), 0.0);
+        assert_eq!(mixer.active_voices(), 0);
+    }
+
+    #[test]
+    fn test_stolen_voice_fades_instead_of_cutting() {
+        let mut mixer = Mixer::new(48_000, 1);
+        mixer.set_voice_limit(2, StealPolicy::SameNoteFirst);
+        mixer.handle(play(60));
+        mixer.handle(play(64));
+        mixer.handle(play(67));
+
+        assert_eq!(mixer.active_voices(), 3);
+        assert_eq!(mixer.stats().stolen(), 1);
+        let stolen: Vec<u8> = mixer
+            .voices
+            .iter()
+            .flatten()
+            .filter(|v| v.stolen)
+            .map(|v| v.midi_note)
+            .collect();
+        assert_eq!(stolen, vec![60]);
+
+        peak(&mut mixer, 480);
+        assert_eq!(mixer.active_voices(), 2);
+    }
+
+    #[test]
+    fn test_release_trigger_waits_for_sustain_and_fades_with_hold() {
+        let stop = AudioCommand::StopNote {
+            midi: 60,
+            release: Some(ReleaseTrigger {
+                sample: Arc::new(AudioSample::new(vec![0.5; 4_800], 48_000, 1)),
+                pitch_ratio: 1.0,
+                gain: 1.0,
+                decay_db_per_sec: 20.0,
+            }),
+        };
+        let release_volume = |mixer: &Mixer| {
+            mixer
+                .voices
+                .iter()
+                .flatten()
+                .find(|v| v.one_shot)
+                .map(|v| v.volume)
+        };
+
+        let mut mixer = Mixer::new(48_000, 1);
+        mixer.handle(AudioCommand::Sustain { value: 127 });
+        mixer.handle(play(60));
+        peak(&mut mixer, 24_000);
+        mixer.handle(stop);
+        assert_eq!(release_volume(&mixer), None);
+
+        mixer.handle(AudioCommand::Sustain { value: 0 });
+        let volume = release_volume(&mixer).unwrap();
+        assert!((volume - 0.316).abs() < 1e-3);
+
+        // A one-shot ignores later note-offs and plays to its end.
+        mixer.handle(AudioCommand::StopNote {
+            midi: 60,
+            release: None,
+        });
+        peak(&mut mixer, 2_400);
+        assert!(release_volume(&mixer).is_some());
+        peak(&mut mixer, 2_400);
+        assert_eq!(mixer.active_voices(), 0);
+    }
+
+    #[test]
+    fn test_mic_positions_count_and_steal_as_one_strike() {
+        let mut mixer = Mixer::new(48_000, 1);
+        mixer.set_voice_limit(2, StealPolicy::Oldest);
+        mixer.handle(with_mic(60));
+        mixer.handle(with_mic(64));
+        assert_eq!(mixer.active_voices(), 4);
+        assert_eq!(mixer.stats().stolen(), 0);
+
+        mixer.handle(with_mic(67));
+        assert_eq!(mixer.stats().stolen(), 1);
+        let stolen: Vec<u8> = mixer
+            .voices
+            .iter()
+            .flatten()
+            .filter(|v| v.stolen)
+            .map(|v| v.midi_note)
+            .collect();
+        assert_eq!(stolen, vec![60, 60]);
+
+        peak(&mut mixer, 480);
+        assert_eq!(mixer.active_voices(), 4);
+        assert_eq!(mixer.stats().active(), 2);
+    }
+
+    #[test]
+    fn test_mic_positions_mix_at_their_levels() {
+        let mut single = Mixer::new(48_000, 1);
+        single.handle(play(60));
+        let single = peak(&mut single, 256);
+
+        let mut mixer = Mixer::new(48_000, 1);
+        mixer.set_mic_levels([1.0, 1.0, 0.5, 1.0]);
+        mixer.handle(with_mic(60));
+        assert_eq!(mixer.active_voices(), 2);
+        assert!((peak(&mut mixer, 256) - single * 1.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_looped_sample_sustains_until_released() {
+        let mut sample = AudioSample::new(vec![0.5; 4_800], 48_000, 1);
+        sample.set_loop(1_000, 4_000, 500);
+        let mut mixer = Mixer::new(48_000, 1);
+        mixer.handle(AudioCommand::PlayNote {
+            midi: 60,
+            velocity: 127,
+            sample: Arc::new(sample),
+            pitch_ratio: 1.0,
+            envelope: EnvelopeParams {
+                release: Some(0.01),
+                ..EnvelopeParams::default()
+            },
+            blend: None,
+            mic: 0,
+            mics: Default::default(),
+        });
+
+        let held = peak(&mut mixer, 256);
+        peak(&mut mixer, 48_000);
+        assert_eq!(mixer.active_voices(), 1);
+        assert!((peak(&mut mixer, 256) - held).abs() < 1e-4);
+
+        mixer.handle(AudioCommand::StopNote {
+            midi: 60,
+            release: None,
+        });
+        peak(&mut mixer, 4_800);
+        assert_eq!(mixer.active_voices(), 0);
+    }
+
+    #[test]
+    fn test_loop_ending_on_last_frame_wraps() {
+        // Loops read from `smpl` chunks usually end on the file's last frame.
+        let mut sample = AudioSample::new(vec![0.5; 4_800], 48_000, 1);
+        sample.set_loop(1_000, 4_800, 0);
+        let mut mixer = Mixer::new(48_000, 1);
+        mixer.set_interpolation(Interpolation::Cubic);
+        mixer.handle(AudioCommand::PlayNote {
+            midi: 60,
+            velocity: 127,
+            sample: Arc::new(sample),
+            pitch_ratio: 1.5,
+            envelope: EnvelopeParams::default(),
+            blend: None,
+            mic: 0,
+            mics: Default::default(),
+        });
+
+        let held = peak(&mut mixer, 256);
+        peak(&mut mixer, 24_000);
+        assert_eq!(mixer.active_voices(), 1);
+
+        // Every read across the seam continues into the loop start.
+        let mut out = vec![0.0; 9_600];
+        mixer.render(&mut out);
+        assert!(out.iter().all(|s| (s - held).abs() < 1e-4));
+    }
+
+    #[test]
+    fn test_undamped_loop_plays_out_after_note_off() {
+        for loop_end in [4_000, 4_800] {
+            let mut sample = AudioSample::new(vec![0.5; 4_800], 48_000, 1);
+            sample.set_loop(1_000, loop_end, 0);
+            let mut mixer = Mixer::new(48_000, 1);
+            mixer.set_resonance(0.0, Some(100));
+            mixer.handle(AudioCommand::PlayNote {
+                midi: 100,
+                velocity: 127,
+                sample: Arc::new(sample),
+                pitch_ratio: 1.0,
+                envelope: EnvelopeParams {
+                    release: Some(0.01),
+                    ..EnvelopeParams::default()
+                },
+                blend: None,
+                mic: 0,
+                mics: Default::default(),
+            });
+            peak(&mut mixer, 24_000);
+
+            mixer.handle(AudioCommand::StopNote {
+                midi: 100,
+                release: None,
+            });
+            peak(&mut mixer, 256);
+            assert_eq!(mixer.active_voices(), 1);
+            peak(&mut mixer, 4_800);
+            assert_eq!(mixer.active_voices(), 0);
+        }
+    }
+
+    #[test]
+    fn test_source_channels_map_onto_bus() {
+        let render = |bus: usize, data: Vec<f32>, src_channels: usize| {
+            let mut mixer = Mixer::new(48_000, bus);
+            mixer.handle(AudioCommand::PlayNote {
+                midi: 60,
+                velocity: 127,
+                sample: Arc::new(AudioSample::new(data, 48_000, src_channels)),
+                pitch_ratio: 1.0,
+                envelope: EnvelopeParams::default(),
+                blend: None,
+                mic: 0,
+                mics: Default::default(),
+            });
+            let mut out = vec![0.0; 1_024 * bus];
+            mixer.render(&mut out);
+            out[out.len() - bus..].to_vec()
+        };
+        let stereo: Vec<f32> = [0.4, 0.2].repeat(4_800);
+
+        let both = render(2, stereo.clone(), 2);
+        assert!(both[1] > 0.0);
+        assert!((both[0] - both[1] * 2.0).abs() < 1e-6);
+
+        let mono = render(2, vec![0.3; 4_800], 1);
+        assert_eq!(mono[0], mono[1]);
+
+        // Folding to mono keeps both sides rather than only the left.
+        let folded = render(1, stereo, 2);
+        assert!((folded[0] - (both[0] + both[1]) / 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_layer_blend_splits_note_across_two_voices() {
+        let mut mixer = Mixer::new(48_000, 1);
+        let sample = Arc::new(AudioSample::new(vec![0.5; 48_000], 48_000, 1));
+        mixer.handle(AudioCommand::PlayNote {
+            midi: 60,
+            velocity: 127,
+            sample: sample.clone(),
+            pitch_ratio: 1.0,
+            envelope: EnvelopeParams::default(),
+            blend: Some((sample, 0.5)),
+            mic: 0,
+            mics: Default::default(),
+        });
+
+        assert_eq!(mixer.active_voices(), 2);
+        let power: f32 = mixer
+            .voices
+            .iter()
+            .flatten()
+            .map(|v| v.volume.powi(2))
+            .sum();
+        assert!((power - 1.0).abs() < 1e-4);
+    }
+}
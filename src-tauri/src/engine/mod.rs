@@ -1,3 +1,21 @@
+pub mod backend;
 pub mod cache;
+pub mod clock;
+pub mod controller;
 pub mod decoder;
+pub mod effects;
+pub mod envelope;
+pub mod impulse;
+pub mod interpolate;
+pub mod limiter;
+pub mod mixer;
+pub mod params;
 pub mod parser;
+pub mod pedal;
+pub mod render;
+pub mod resonance;
+pub mod sample;
+pub mod stereo;
+pub mod tuning;
+pub mod velocity;
+pub mod writer;
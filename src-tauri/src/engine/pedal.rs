@@ -0,0 +1,60 @@
+// Pedal travel below PEDAL_UP reads as fully lifted and above PEDAL_DOWN as
+// fully pressed, which leaves some slack at both ends of continuous pedals.
+// Switch pedals only ever send 0 and 127.
+const PEDAL_UP: u8 = 16;
+
+const PEDAL_DOWN: u8 = 112;
+
+// Una corda shifts the hammers onto fewer strings: softer layers and a
+// slightly quieter, duller note at full travel.
+const SOFT_VELOCITY_SCALE: f32 = 0.75;
+
+const SOFT_GAIN: f32 = 0.8;
+
+pub fn pedal_position(value: u8) -> f32 {
+    let value = value.min(127);
+    ((value as f32 - PEDAL_UP as f32) / (PEDAL_DOWN - PEDAL_UP) as f32).clamp(0.0, 1.0)
+}
+
+// How strongly the dampers act on strings whose keys are up: 1.0 with the
+// pedal lifted, 0.0 with it fully down and anything in between half-pedaling.
+pub fn damping(value: u8) -> f32 {
+    1.0 - pedal_position(value)
+}
+
+pub fn is_pressed(value: u8) -> bool {
+    value >= 64
+}
+
+pub fn soft_velocity(velocity: u8, soft: f32) -> u8 {
+    let scale = 1.0 - (1.0 - SOFT_VELOCITY_SCALE) * soft.clamp(0.0, 1.0);
+    ((velocity as f32 * scale).round() as u8).max(1)
+}
+
+pub fn soft_gain(soft: f32) -> f32 {
+    1.0 - (1.0 - SOFT_GAIN) * soft.clamp(0.0, 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_damping_curve() {
+        assert_eq!(damping(0), 1.0);
+        assert_eq!(damping(127), 0.0);
+        assert_eq!(damping(PEDAL_UP), 1.0);
+        assert_eq!(damping(PEDAL_DOWN), 0.0);
+
+        let half = damping(64);
+        assert!(half > 0.4 && half < 0.6);
+    }
+
+    #[test]
+    fn test_soft_pedal_lowers_velocity() {
+        assert_eq!(soft_velocity(100, 0.0), 100);
+        assert_eq!(soft_velocity(100, 1.0), 75);
+        assert_eq!(soft_velocity(1, 1.0), 1);
+        assert_eq!(soft_gain(0.0), 1.0);
+    }
+}
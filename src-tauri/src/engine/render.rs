@@ -0,0 +1,274 @@
+use crate::engine::effects::{self, MasterParams};
+use crate::engine::impulse;
+use crate::engine::interpolate::Interpolation;
+use crate::engine::mixer::{AudioCommand, Mixer, ScheduledCommand, StealPolicy, MAX_MICS};
+use crate::engine::stereo::StereoImage;
+use crate::engine::tuning::Tuning;
+use crate::engine::velocity::VelocityCurve;
+use crate::extra::challenge::buffer::MidiNoteMs;
+use crate::setup::audio::{self, RoundRobinTurns};
+use crate::setup::config::InstrumentConfig;
+
+const BLOCK_FRAMES: usize = 512;
+
+const MAX_TAIL_SECS: u32 = 10;
+
+// Seed for Random round-robin, so renders of one session always match.
+const ROUND_ROBIN_SEED: u64 = 0;
+
+// Drives a `Mixer` without an audio device, as fast as the CPU allows.
+pub struct OfflineRenderer {
+    mixer: Mixer,
+    effects_tail: u64,
+    // Transpose is applied when notes are scheduled, the rest in the mixer.
+    tuning: Tuning,
+    // The user's curve over the instrument's, applied when notes are scheduled.
+    velocity_curve: Option<VelocityCurve>,
+}
+
+impl OfflineRenderer {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        Self {
+            mixer: Mixer::new(sample_rate, channels),
+            effects_tail: 0,
+            tuning: Tuning::default(),
+            velocity_curve: None,
+        }
+    }
+
+    // Runs the mix through the master chain and keeps rendering after the
+    // last voice for as long as the reverb or impulse response rings.
+    pub fn set_effects(&mut self, params: &MasterParams) {
+        let chain = effects::build_chain(params, self.sample_rate());
+        self.mixer.set_effects(Box::new(chain));
+
+        let mut tail_secs = 0.0f32;
+        if params.reverb_mix > 0.0 {
+            tail_secs = params.reverb_time.max(0.0);
+        }
+        if let Some(sample) = params
+            .convolution_ir
+            .as_deref()
+            .filter(|_| params.convolution_mix > 0.0)
+            .and_then(|name| impulse::load(name).ok())
+        {
+            let predelay = params.convolution_predelay_ms.max(0.0) / 1000.0;
+            tail_secs = tail_secs.max(impulse::length_secs(&sample) + predelay);
+        }
+        self.effects_tail = (tail_secs * self.sample_rate() as f32) as u64;
+    }
+
+    pub fn set_interpolation(&mut self, mode: Interpolation) {
+        self.mixer.set_interpolation(mode);
+    }
+
+    pub fn set_voice_limit(&mut self, polyphony: usize, policy: StealPolicy) {
+        self.mixer.set_voice_limit(polyphony, policy);
+    }
+
+    pub fn set_stereo_image(&mut self, image: StereoImage) {
+        self.mixer.set_stereo_image(image);
+    }
+
+    pub fn set_mic_levels(&mut self, levels: [f32; MAX_MICS]) {
+        self.mixer.set_mic_levels(levels);
+    }
+
+    pub fn set_tuning(&mut self, tuning: Tuning) {
+        self.tuning = tuning;
+        self.mixer.set_tuning(tuning.ratio());
+    }
+
+    pub fn set_velocity_curve(&mut self, curve: VelocityCurve) {
+        self.velocity_curve = Some(curve);
+    }
+
+    pub fn set_resonance(&mut self, amount: f32, undamped_from: Option<u8>) {
+        self.mixer.set_resonance(amount, undamped_from);
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.mixer.sample_rate()
+    }
+
+    pub fn channels(&self) -> usize {
+        self.mixer.channels()
+    }
+
+    // Renders every command at its frame, then keeps going until all voices
+    // and the effects tail have died out (bounded by MAX_TAIL_SECS). Returns
+    // interleaved frames, shifted back by the limiter's look-ahead so notes
+    // land exactly on their frame.
+    pub fn render(&mut self, mut events: Vec<ScheduledCommand>) -> Vec<f32> {
+        events.sort_by_key(|e| e.frame);
+
+        let channels = self.channels();
+        let last_frame = events.last().map(|e| e.frame).unwrap_or(0);
+        let max_tail = (self.sample_rate() * MAX_TAIL_SECS) as u64;
+
+        let mut output: Vec<f32> = Vec::with_capacity(last_frame as usize * channels);
+        let mut block = vec![0.0f32; BLOCK_FRAMES * channels];
+        let mut events = events.into_iter().peekable();
+        let mut frame: u64 = 0;
+        let mut tail_end: Option<u64> = None;
+
+        loop {
+            while let Some(event) = events.next_if(|e| e.frame <= frame) {
+                self.mixer.handle(event.command);
+            }
+
+            let remaining = match events.peek() {
+                Some(next) => (next.frame - frame) as usize,
+                None if frame >= last_frame + max_tail => break,
+                None if self.mixer.active_voices() == 0 => {
+                    let end = *tail_end.get_or_insert(frame + self.effects_tail);
+                    if frame >= end {
+                        break;
+                    }
+                    BLOCK_FRAMES
+                }
+                None => BLOCK_FRAMES,
+            };
+
+            let frames = remaining.min(BLOCK_FRAMES);
+            let buf = &mut block[..frames * channels];
+            self.mixer.render(buf);
+            output.extend_from_slice(buf);
+            frame += frames as u64;
+        }
+
+        let latency = self.mixer.latency() * channels;
+        let mut flush = vec![0.0f32; latency];
+        self.mixer.render(&mut flush);
+        output.extend_from_slice(&flush);
+        output.drain(..latency.min(output.len()));
+        output
+    }
+}
+
+fn ms_to_frame(ms: u32, sample_rate: u32) -> u64 {
+    ms as u64 * sample_rate as u64 / 1000
+}
+
+// Turns a session's notes into note-on/off commands. Notes the instrument
+// cannot play are skipped, the same way the live batch player skips them.
+pub fn schedule_notes(
+    config: &InstrumentConfig,
+    notes: &[MidiNoteMs],
+    sample_rate: u32,
+    tuning: Tuning,
+    curve: &VelocityCurve,
+) -> Vec<ScheduledCommand> {
+    let mut events = Vec::with_capacity(notes.len() * 2);
+    let turns = RoundRobinTurns::new(Some(ROUND_ROBIN_SEED));
+
+    for note in notes {
+        let velocity = curve.apply(note.velocity);
+        let Some(key) = tuning.transpose_key(note.midi) else {
+            continue;
+        };
+        let Ok(command) = audio::note_command(config, key, velocity, None, 0.0, &turns) else {
+            continue;
+        };
+        let start = ms_to_frame(note.start_ms, sample_rate);
+        let end = ms_to_frame(note.start_ms + note.duration_ms, sample_rate);
+
+        events.push(ScheduledCommand {
+            frame: start,
+            command,
+        });
+        events.push(ScheduledCommand {
+            frame: end.max(start + 1),
+            command: AudioCommand::StopNote {
+                midi: key,
+                release: audio::release_trigger(config, key, &turns),
+            },
+        });
+    }
+
+    // A note-off and a retrigger of the same key on one frame must release
+    // the old voice first, so stops sort ahead of starts.
+    events.sort_by_key(|e| (e.frame, matches!(e.command, AudioCommand::PlayNote { .. })));
+    events
+}
+
+// Renders `notes` on a renderer already set up with the user's effects and
+// voice settings; what the instrument itself defines comes from `config`.
+pub fn render_notes(
+    mut renderer: OfflineRenderer,
+    config: &InstrumentConfig,
+    notes: &[MidiNoteMs],
+) -> Vec<f32> {
+    let curve = renderer
+        .velocity_curve
+        .clone()
+        .unwrap_or_else(|| config.velocity_curve());
+    let events = schedule_notes(
+        config,
+        notes,
+        renderer.sample_rate(),
+        renderer.tuning,
+        &curve,
+    );
+    renderer.set_voice_limit(config.polyphony(), config.steal_policy());
+    renderer.set_resonance(config.resonance(), config.undamped_from());
+    renderer.render(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::envelope::EnvelopeParams;
+    use crate::engine::sample::AudioSample;
+    use std::sync::Arc;
+
+    fn sine(freq: f32, sample_rate: u32, secs: f32, channels: usize) -> Arc<AudioSample> {
+        let frames = (sample_rate as f32 * secs) as usize;
+        let mut data = Vec::with_capacity(frames * channels);
+        for i in 0..frames {
+            let s = (i as f32 * freq * std::f32::consts::TAU / sample_rate as f32).sin() * 0.5;
+            for _ in 0..channels {
+                data.push(s);
+            }
+        }
+        Arc::new(AudioSample::new(data, sample_rate, channels))
+    }
+
+    fn play(frame: u64, sample: Arc<AudioSample>) -> ScheduledCommand {
+        ScheduledCommand {
+            frame,
+            command: AudioCommand::PlayNote {
+                midi: 69,
+                velocity: 127,
+                sample,
+                pitch_ratio: 1.0,
+                envelope: EnvelopeParams::default(),
+                blend: None,
+                mic: 0,
+                mics: Default::default(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_render_is_deterministic() {
+        let sample = sine(440.0, 48_000, 0.5, 2);
+        let events = || vec![play(100, sample.clone())];
+
+        let a = OfflineRenderer::new(48_000, 2).render(events());
+        let b = OfflineRenderer::new(48_000, 2).render(events());
+
+        assert_eq!(a, b);
+        assert!(a[..200].iter().all(|s| *s == 0.0));
+        assert!(a.iter().any(|s| s.abs() > 0.1));
+    }
+
+    #[test]
+    fn test_render_corrects_sample_rate() {
+        let sample = sine(440.0, 44_100, 1.0, 1);
+        let out = OfflineRenderer::new(48_000, 2).render(vec![play(0, sample)]);
+
+        let frames = out.len() / 2;
+        assert!((frames as i64 - 48_000).abs() < BLOCK_FRAMES as i64);
+    }
+}
@@ -0,0 +1,193 @@
+use crate::engine::tuning::MIN_RATIO;
+
+// Range of modelled strings, A0 to C8.
+const LOWEST_STRING: u8 = 21;
+
+const HIGHEST_STRING: u8 = 108;
+
+// Time for a free string's sympathetic ring to fall by 60 dB.
+const RING_SECS: f32 = 4.0;
+
+// One-pole lowpass inside each string loop, so upper partials die sooner.
+const LOOP_LOWPASS: f32 = 0.6;
+
+// Dampers landing or lifting are ramped over this time.
+const FREEDOM_SMOOTHING_SECS: f32 = 0.01;
+
+// One string as a fractional-delay feedback loop tuned to its key. The loop
+// rings at every harmonic of the key, so it picks up whatever in the mix
+// shares those partials. `freedom` is how far its damper is lifted.
+struct StringLoop {
+    freq: f32,
+    sample_rate: f32,
+    delay: Vec<f32>,
+    length: f32,
+    pos: usize,
+    lowpass: f32,
+    feedback: f32,
+    freedom: f32,
+    target: f32,
+}
+
+impl StringLoop {
+    fn new(midi: u8, sample_rate: f32) -> Self {
+        let freq = 440.0 * 2.0f32.powf((midi as f32 - 69.0) / 12.0);
+        // Long enough for the lowest tuning the engine allows.
+        let longest = sample_rate / (freq * MIN_RATIO);
+        let mut string = Self {
+            freq,
+            sample_rate,
+            delay: vec![0.0; longest.ceil() as usize + 2],
+            length: 0.0,
+            pos: 0,
+            lowpass: 0.0,
+            feedback: 0.0,
+            freedom: 0.0,
+            target: 0.0,
+        };
+        string.tune(1.0);
+        string
+    }
+
+    fn tune(&mut self, ratio: f32) {
+        let period = self.sample_rate / (self.freq * ratio.max(MIN_RATIO));
+        // The loop lowpass adds its own group delay at low frequencies.
+        let lowpass_delay = (1.0 - LOOP_LOWPASS) / LOOP_LOWPASS;
+        self.length = (period - lowpass_delay).clamp(2.0, (self.delay.len() - 2) as f32);
+        self.feedback = 10f32.powf(-3.0 * period / (self.sample_rate * RING_SECS));
+    }
+
+    fn is_silent(&self) -> bool {
+        self.target == 0.0 && self.freedom == 0.0
+    }
+
+    fn process(&mut self, input: f32, smoothing: f32) -> f32 {
+        let len = self.delay.len();
+        let read = self.pos as f32 + len as f32 - self.length;
+        let idx = read as usize;
+        let frac = read - idx as f32;
+        let delayed = self.delay[idx % len] * (1.0 - frac) + self.delay[(idx + 1) % len] * frac;
+        self.lowpass += (delayed - self.lowpass) * LOOP_LOWPASS;
+
+        self.freedom = self.target + (self.freedom - self.target) * smoothing;
+        if (self.freedom - self.target).abs() < 1e-4 {
+            self.freedom = self.target;
+        }
+
+        // Input is scaled so a string driven at its own pitch settles near
+        // the input level rather than growing with its ring time.
+        let excite = input * (1.0 - self.feedback) * self.freedom;
+        self.delay[self.pos] = excite + self.lowpass * self.feedback * self.freedom;
+        self.pos = (self.pos + 1) % len;
+        self.lowpass * self.freedom
+    }
+}
+
+// Sympathetic resonance of the strings whose dampers are off: held keys,
+// everything while the sustain pedal is down, and the undamped top register.
+// The voice mix excites every free string and their ringing is mixed back in
+// at `amount`. Delay lines are sized up front, so `process` neither locks nor
+// allocates.
+pub struct Resonance {
+    strings: Vec<StringLoop>,
+    amount: f32,
+    smoothing: f32,
+}
+
+impl Resonance {
+    pub fn new(sample_rate: u32) -> Self {
+        let sample_rate = sample_rate as f32;
+        Self {
+            strings: (LOWEST_STRING..=HIGHEST_STRING)
+                .map(|midi| StringLoop::new(midi, sample_rate))
+                .collect(),
+            amount: 0.0,
+            smoothing: (-1.0 / (FREEDOM_SMOOTHING_SECS * sample_rate)).exp(),
+        }
+    }
+
+    pub fn set_amount(&mut self, amount: f32) {
+        self.amount = amount.clamp(0.0, 1.0);
+        if self.amount == 0.0 {
+            for string in &mut self.strings {
+                string.delay.fill(0.0);
+                string.lowpass = 0.0;
+                string.freedom = 0.0;
+            }
+        }
+    }
+
+    // Retunes every string by `ratio`, following the engine's tuning.
+    pub fn set_tuning(&mut self, ratio: f32) {
+        for string in &mut self.strings {
+            string.tune(ratio);
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.amount > 0.0
+    }
+
+    // Sets how far the damper of `midi` is off its string, 0 to 1.
+    pub fn set_free(&mut self, midi: u8, freedom: f32) {
+        if let Some(string) = midi
+            .checked_sub(LOWEST_STRING)
+            .and_then(|i| self.strings.get_mut(i as usize))
+        {
+            string.target = freedom.clamp(0.0, 1.0);
+        }
+    }
+
+    pub fn process(&mut self, mix: &mut [f32], channels: usize) {
+        if !self.is_enabled() {
+            return;
+        }
+        let smoothing = self.smoothing;
+        for frame in mix.chunks_exact_mut(channels) {
+            let input = frame.iter().sum::<f32>() / channels as f32;
+            let mut ring = 0.0;
+            for string in self.strings.iter_mut().filter(|s| !s.is_silent()) {
+                ring += string.process(input, smoothing);
+            }
+            frame.iter_mut().for_each(|s| *s += ring * self.amount);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ring_after(resonance: &mut Resonance, freq: f32) -> f32 {
+        let mut mix: Vec<f32> = (0..24_000)
+            .map(|i| (i as f32 * freq * std::f32::consts::TAU / 48_000.0).sin() * 0.5)
+            .collect();
+        resonance.process(&mut mix, 1);
+
+        // Silence after the note: whatever remains is the strings ringing on.
+        let mut tail = vec![0.0; 4_800];
+        resonance.process(&mut tail, 1);
+        tail.iter().fold(0.0f32, |m, s| m.max(s.abs()))
+    }
+
+    #[test]
+    fn test_free_string_rings_with_its_harmonics() {
+        // C3 held, C4 played: the second harmonic of C3 is excited.
+        let mut resonance = Resonance::new(48_000);
+        resonance.set_amount(1.0);
+        resonance.set_free(48, 1.0);
+        let related = ring_after(&mut resonance, 261.63);
+
+        let mut resonance = Resonance::new(48_000);
+        resonance.set_amount(1.0);
+        resonance.set_free(48, 1.0);
+        let unrelated = ring_after(&mut resonance, 277.18);
+
+        let mut resonance = Resonance::new(48_000);
+        resonance.set_amount(1.0);
+        let damped = ring_after(&mut resonance, 261.63);
+
+        assert!(related > unrelated * 4.0, "{} vs {}", related, unrelated);
+        assert_eq!(damped, 0.0);
+    }
+}
@@ -0,0 +1,59 @@
+// Frames a voice repeats until its envelope has run out. `end` is
+// exclusive; the last `crossfade` frames before it fade into the frames
+// before `start`, hiding the seam.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SampleLoop {
+    pub start: usize,
+    pub end: usize,
+    pub crossfade: usize,
+}
+
+impl SampleLoop {
+    pub fn frames(&self) -> usize {
+        self.end - self.start
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AudioSample {
+    pub data: Vec<f32>,
+    pub sample_rate: u32,
+    pub channels: usize,
+    pub loop_region: Option<SampleLoop>,
+}
+
+impl AudioSample {
+    pub fn new(data: Vec<f32>, sample_rate: u32, channels: usize) -> Self {
+        Self {
+            data,
+            sample_rate,
+            channels: channels.max(1),
+            loop_region: None,
+        }
+    }
+
+    // Loops `start` to `end` (exclusive). Regions outside the data or too
+    // short to play are ignored, and the crossfade is cut to what fits.
+    pub fn set_loop(&mut self, start: usize, end: usize, crossfade: usize) {
+        let end = end.min(self.frames());
+        self.loop_region = (start + 1 < end).then(|| SampleLoop {
+            start,
+            end,
+            crossfade: crossfade.min(start).min(end - start),
+        });
+    }
+
+    pub fn frames(&self) -> usize {
+        self.data.len() / self.channels
+    }
+
+    pub fn duration_secs(&self) -> f32 {
+        self.frames() as f32 / self.sample_rate as f32
+    }
+
+    // Playhead step per output frame that keeps the recording at its original
+    // pitch when the stream runs at a different rate than the file.
+    pub fn rate_ratio(&self, output_rate: u32) -> f32 {
+        self.sample_rate as f32 / output_rate as f32
+    }
+}
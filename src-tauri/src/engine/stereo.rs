@@ -0,0 +1,108 @@
+use serde::{Deserialize, Serialize};
+use std::f32::consts::{FRAC_PI_4, SQRT_2};
+
+const LOWEST_KEY: f32 = 21.0;
+
+const HIGHEST_KEY: f32 = 108.0;
+
+// Pan position of the outermost keys, short of hard left and right.
+const KEY_SPREAD: f32 = 0.7;
+
+pub const MAX_WIDTH: f32 = 2.0;
+
+// Where mono samples are placed across the stereo field. Stored as
+// `"player"` in instrument settings and the user's state file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyPanning {
+    // Every key in the centre.
+    #[default]
+    Off,
+    // Bass on the left, as heard from the bench.
+    Player,
+    // Bass on the right, as heard from the hall.
+    Audience,
+}
+
+impl KeyPanning {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "off" | "none" => Some(Self::Off),
+            "player" => Some(Self::Player),
+            "audience" => Some(Self::Audience),
+            _ => None,
+        }
+    }
+}
+
+// Key panning for mono samples and width for stereo ones. Both only apply
+// on stereo buses.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct StereoImage {
+    pub panning: KeyPanning,
+    // 0 folds stereo samples to mono, 1 leaves them as recorded and up to
+    // MAX_WIDTH exaggerates the difference between channels.
+    pub width: f32,
+}
+
+impl Default for StereoImage {
+    fn default() -> Self {
+        Self {
+            panning: KeyPanning::Off,
+            width: 1.0,
+        }
+    }
+}
+
+impl StereoImage {
+    // Pan position of a key, -1 (left) to 1 (right).
+    pub fn key_pan(&self, midi: u8) -> f32 {
+        let across = ((midi as f32 - LOWEST_KEY) / (HIGHEST_KEY - LOWEST_KEY)).clamp(0.0, 1.0);
+        let pan = (across * 2.0 - 1.0) * KEY_SPREAD;
+        match self.panning {
+            KeyPanning::Off => 0.0,
+            KeyPanning::Player => pan,
+            KeyPanning::Audience => -pan,
+        }
+    }
+}
+
+// Equal-power left and right gains, normalised so the centre is unity and
+// turning panning on does not change the level of middle keys.
+pub fn pan_gains(pan: f32) -> [f32; 2] {
+    let angle = (pan.clamp(-1.0, 1.0) + 1.0) * FRAC_PI_4;
+    [angle.cos() * SQRT_2, angle.sin() * SQRT_2]
+}
+
+// Mid/side width on one stereo frame.
+pub fn widen(left: f32, right: f32, width: f32) -> (f32, f32) {
+    let mid = (left + right) * 0.5;
+    let side = (left - right) * 0.5 * width;
+    (mid + side, mid - side)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_pan_follows_perspective() {
+        let player = StereoImage {
+            panning: KeyPanning::Player,
+            width: 1.0,
+        };
+        let audience = StereoImage {
+            panning: KeyPanning::Audience,
+            width: 1.0,
+        };
+        assert!(player.key_pan(21) < -0.6);
+        assert!(player.key_pan(108) > 0.6);
+        assert_eq!(audience.key_pan(21), -player.key_pan(21));
+        assert_eq!(StereoImage::default().key_pan(21), 0.0);
+
+        let [left, right] = pan_gains(0.0);
+        assert!((left - 1.0).abs() < 1e-6 && (right - 1.0).abs() < 1e-6);
+        assert_eq!(widen(1.0, 0.0, 0.0), (0.5, 0.5));
+        assert_eq!(widen(1.0, 0.0, 1.0), (1.0, 0.0));
+    }
+}
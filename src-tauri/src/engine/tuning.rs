@@ -0,0 +1,90 @@
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_REFERENCE: f32 = 440.0;
+
+pub const MIN_REFERENCE: f32 = 392.0;
+
+pub const MAX_REFERENCE: f32 = 494.0;
+
+pub const MAX_TRANSPOSE: i32 = 24;
+
+pub const MAX_FINE_TUNE_CENTS: f32 = 100.0;
+
+// Lowest ratio `Tuning::ratio` can give, for sizing anything tuned to it.
+pub const MIN_RATIO: f32 = MIN_REFERENCE / DEFAULT_REFERENCE * 0.943_874_3;
+
+// The user's tuning, applied to everything the engine plays. Transposing
+// moves notes to other keys so each keeps its own samples; fine tune and the
+// A4 reference retune the whole engine.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Tuning {
+    pub transpose: i32,
+    pub fine_tune: f32,
+    pub reference: f32,
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        Self {
+            transpose: 0,
+            fine_tune: 0.0,
+            reference: DEFAULT_REFERENCE,
+        }
+    }
+}
+
+impl Tuning {
+    pub fn clamped(self) -> Self {
+        Self {
+            transpose: self.transpose.clamp(-MAX_TRANSPOSE, MAX_TRANSPOSE),
+            fine_tune: self
+                .fine_tune
+                .clamp(-MAX_FINE_TUNE_CENTS, MAX_FINE_TUNE_CENTS),
+            reference: self.reference.clamp(MIN_REFERENCE, MAX_REFERENCE),
+        }
+    }
+
+    // Pitch ratio from fine tune and reference, 1.0 at A440.
+    pub fn ratio(&self) -> f32 {
+        let tuning = self.clamped();
+        tuning.reference / DEFAULT_REFERENCE * 2.0f32.powf(tuning.fine_tune / 1200.0)
+    }
+
+    // Key that sounds for `midi`, or `None` when transposed off the keyboard.
+    pub fn transpose_key(&self, midi: u8) -> Option<u8> {
+        let key = midi as i32 + self.clamped().transpose;
+        u8::try_from(key).ok().filter(|key| *key < 128)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reference_and_fine_tune_ratio() {
+        assert_eq!(Tuning::default().ratio(), 1.0);
+
+        let baroque = Tuning {
+            reference: 415.0,
+            ..Tuning::default()
+        };
+        // A415 is almost exactly a semitone below A440.
+        assert!((baroque.ratio() - 2.0f32.powf(-1.0 / 12.0)).abs() < 1e-3);
+
+        let lowest = Tuning {
+            fine_tune: -1_000.0,
+            reference: 0.0,
+            ..Tuning::default()
+        };
+        assert!((lowest.ratio() - MIN_RATIO).abs() < 1e-5);
+
+        let up = Tuning {
+            transpose: 3,
+            ..Tuning::default()
+        };
+        assert_eq!(up.transpose_key(60), Some(63));
+        assert_eq!(up.transpose_key(126), None);
+    }
+}
@@ -0,0 +1,119 @@
+use crate::extra::sketch::instrument::settings::Settings;
+use serde::{Deserialize, Serialize};
+
+const SOFT_EXPONENT: f32 = 0.6;
+
+const HARD_EXPONENT: f32 = 1.6;
+
+const LOG_STEEPNESS: f32 = 9.0;
+
+// Maps the velocity a keyboard sends to the velocity used for layer
+// selection and gain. Stored as `"soft"` or `{"custom": [[0, 0], [127, 127]]}`
+// in both instrument settings and the user's state file.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VelocityCurve {
+    #[default]
+    Linear,
+    Soft,
+    Hard,
+    Logarithmic,
+    Custom(Vec<(u8, u8)>),
+}
+
+impl VelocityCurve {
+    pub fn from_settings(settings: &Settings) -> Self {
+        settings
+            .values
+            .get("velocity_curve")
+            .and_then(|v| serde_json::from_value(v.clone()).ok())
+            .unwrap_or_default()
+    }
+
+    // Velocity 0 stays 0 so note-offs sent as note-ons keep working; every
+    // other input lands in 1..=127.
+    pub fn apply(&self, velocity: u8) -> u8 {
+        if velocity == 0 {
+            return 0;
+        }
+        let x = velocity.min(127) as f32 / 127.0;
+        let y = match self {
+            Self::Linear => x,
+            Self::Soft => x.powf(SOFT_EXPONENT),
+            Self::Hard => x.powf(HARD_EXPONENT),
+            Self::Logarithmic => (1.0 + LOG_STEEPNESS * x).ln() / (1.0 + LOG_STEEPNESS).ln(),
+            Self::Custom(points) => return interpolate(points, velocity).clamp(1, 127),
+        };
+        ((y * 127.0).round() as u8).clamp(1, 127)
+    }
+}
+
+// Piecewise-linear through the points, flat beyond the first and last ones.
+fn interpolate(points: &[(u8, u8)], velocity: u8) -> u8 {
+    let mut sorted = points.to_vec();
+    sorted.sort_by_key(|(input, _)| *input);
+
+    let (Some(first), Some(last)) = (sorted.first(), sorted.last()) else {
+        return velocity;
+    };
+    if velocity <= first.0 {
+        return first.1;
+    }
+    if velocity >= last.0 {
+        return last.1;
+    }
+
+    sorted
+        .windows(2)
+        .find(|w| velocity >= w[0].0 && velocity <= w[1].0)
+        .map(|w| {
+            let (x0, y0) = (w[0].0 as f32, w[0].1 as f32);
+            let (x1, y1) = (w[1].0 as f32, w[1].1 as f32);
+            let t = if x1 > x0 {
+                (velocity as f32 - x0) / (x1 - x0)
+            } else {
+                0.0
+            };
+            (y0 + (y1 - y0) * t).round() as u8
+        })
+        .unwrap_or(velocity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_curve_shapes() {
+        assert_eq!(VelocityCurve::Linear.apply(64), 64);
+        assert!(VelocityCurve::Soft.apply(64) > 64);
+        assert!(VelocityCurve::Hard.apply(64) < 64);
+        assert!(VelocityCurve::Logarithmic.apply(32) > 32);
+        assert_eq!(VelocityCurve::Hard.apply(1), 1);
+        assert_eq!(VelocityCurve::Soft.apply(0), 0);
+        assert_eq!(VelocityCurve::Soft.apply(127), 127);
+
+        let custom = VelocityCurve::Custom(vec![(127, 127), (0, 20), (64, 100)]);
+        assert_eq!(custom.apply(1), 21);
+        assert_eq!(custom.apply(32), 60);
+        assert_eq!(custom.apply(64), 100);
+    }
+
+    #[test]
+    fn test_curve_from_settings() {
+        let settings: Settings = serde_json::json!({ "velocity_curve": "hard" }).into();
+        assert_eq!(VelocityCurve::from_settings(&settings), VelocityCurve::Hard);
+
+        let settings: Settings =
+            serde_json::json!({ "velocity_curve": { "custom": [[0, 0], [127, 90]] } }).into();
+        assert_eq!(
+            VelocityCurve::from_settings(&settings),
+            VelocityCurve::Custom(vec![(0, 0), (127, 90)])
+        );
+
+        assert_eq!(
+            VelocityCurve::from_settings(&Settings::new()),
+            VelocityCurve::Linear
+        );
+    }
+}
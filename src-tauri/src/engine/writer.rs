@@ -0,0 +1,93 @@
+use crate::error::{AudioError, Result};
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const BITS_PER_SAMPLE: u16 = 16;
+
+const HEADER_LEN: u32 = 44;
+
+// Streams interleaved f32 frames into a 16-bit PCM WAV file. The RIFF sizes
+// are patched in by `finish`, so the length does not need to be known upfront.
+pub struct WavWriter<W: Write + Seek> {
+    out: W,
+    sample_rate: u32,
+    channels: u16,
+    samples_written: u32,
+}
+
+impl WavWriter<BufWriter<File>> {
+    pub fn create(path: &Path, sample_rate: u32, channels: usize) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| AudioError::RenderError(format!("Cannot create output dir: {}", e)))?;
+        }
+
+        let file = File::create(path)
+            .map_err(|e| AudioError::RenderError(format!("Cannot create {:?}: {}", path, e)))?;
+
+        WavWriter::new(BufWriter::new(file), sample_rate, channels)
+            .map_err(|e| AudioError::RenderError(format!("Cannot write {:?}: {}", path, e)))
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(out: W, sample_rate: u32, channels: usize) -> std::io::Result<Self> {
+        let mut writer = Self {
+            out,
+            sample_rate,
+            channels: channels.max(1) as u16,
+            samples_written: 0,
+        };
+        writer.write_header()?;
+        Ok(writer)
+    }
+
+    fn write_header(&mut self) -> std::io::Result<()> {
+        let block_align = self.channels * (BITS_PER_SAMPLE / 8);
+        let byte_rate = self.sample_rate * block_align as u32;
+        let data_len = self.samples_written * (BITS_PER_SAMPLE / 8) as u32;
+
+        self.out.write_all(b"RIFF")?;
+        self.out.write_all(&(HEADER_LEN - 8 + data_len).to_le_bytes())?;
+        self.out.write_all(b"WAVE")?;
+
+        self.out.write_all(b"fmt ")?;
+        self.out.write_all(&16u32.to_le_bytes())?;
+        self.out.write_all(&1u16.to_le_bytes())?;
+        self.out.write_all(&self.channels.to_le_bytes())?;
+        self.out.write_all(&self.sample_rate.to_le_bytes())?;
+        self.out.write_all(&byte_rate.to_le_bytes())?;
+        self.out.write_all(&block_align.to_le_bytes())?;
+        self.out.write_all(&BITS_PER_SAMPLE.to_le_bytes())?;
+
+        self.out.write_all(b"data")?;
+        self.out.write_all(&data_len.to_le_bytes())
+    }
+
+    pub fn write(&mut self, samples: &[f32]) -> std::io::Result<()> {
+        for s in samples {
+            let pcm = (s.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+            self.out.write_all(&pcm.to_le_bytes())?;
+        }
+        self.samples_written += samples.len() as u32;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> std::io::Result<W> {
+        self.out.seek(SeekFrom::Start(0))?;
+        self.write_header()?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+// Writes interleaved f32 frames as a 16-bit PCM WAV file.
+pub fn write_wav(path: &Path, samples: &[f32], sample_rate: u32, channels: usize) -> Result<()> {
+    let mut writer = WavWriter::create(path, sample_rate, channels)?;
+    writer
+        .write(samples)
+        .and_then(|_| writer.finish().map(|_| ()))
+        .map_err(|e| AudioError::RenderError(format!("Cannot write {:?}: {}", path, e)))
+}
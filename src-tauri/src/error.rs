@@ -35,6 +35,9 @@ pub enum AudioError {
     
     #[error("Cache error: {0}")]
     CacheError(String),
+
+    #[error("Render error: {0}")]
+    RenderError(String),
 }
 
 pub type Result<T> = std::result::Result<T, AudioError>;
\ No newline at end of file
@@ -1,34 +1,36 @@
-use std::sync::{Arc, Mutex};
+use crate::engine::params::AtomicF32;
 
 pub struct ReleaseRates {
-    pub fast: f32,
-    pub slow: f32,
+    pub fast: AtomicF32,
+    pub slow: AtomicF32,
 }
 
-impl Default for ReleaseRates {
-    fn default() -> Self {
+impl ReleaseRates {
+    pub const fn new(fast: f32, slow: f32) -> Self {
         Self {
-            fast: 0.9998,
-            slow: 0.99999,
+            fast: AtomicF32::new(fast),
+            slow: AtomicF32::new(slow),
         }
     }
 }
 
-lazy_static::lazy_static! {
-    pub static ref RELEASE_RATES: Arc<Mutex<ReleaseRates>> =
-        Arc::new(Mutex::new(ReleaseRates::default()));
+impl Default for ReleaseRates {
+    fn default() -> Self {
+        Self::new(0.9998, 1.0)
+    }
 }
 
+pub static RELEASE_RATES: ReleaseRates = ReleaseRates::new(0.9998, 1.0);
+
 pub fn set(fast: f32, slow: f32) {
-    let mut rates = RELEASE_RATES.lock().unwrap();
-    rates.fast = fast;
-    rates.slow = slow;
+    RELEASE_RATES.fast.store(fast);
+    RELEASE_RATES.slow.store(slow);
 }
 
 pub fn get_fast() -> f32 {
-    RELEASE_RATES.lock().unwrap().fast
+    RELEASE_RATES.fast.load()
 }
 
 pub fn get_slow() -> f32 {
-    RELEASE_RATES.lock().unwrap().slow
+    RELEASE_RATES.slow.load()
 }
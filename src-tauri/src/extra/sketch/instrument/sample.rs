@@ -1,9 +1,71 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// Mic position of each sample's `path`; other positions are named in `mics`.
+pub const MAIN_MIC: &str = "main";
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct SampleInfo {
     pub path: String,
+    #[serde(default)]
     pub layer: String,
+    #[serde(default)]
+    pub una_corda: bool,
+    // The same take recorded from other mic positions, by position name.
+    #[serde(default)]
+    pub mics: HashMap<String, String>,
+    // Loop points in frames, `loop_end` being the loop's last frame as in WAV
+    // `smpl` chunks. Each one set here overrides the file's own.
+    #[serde(default)]
+    pub loop_start: Option<usize>,
+    #[serde(default)]
+    pub loop_end: Option<usize>,
+    #[serde(default)]
+    pub loop_crossfade: Option<usize>,
+}
+
+impl SampleInfo {
+    pub fn has_loop_settings(&self) -> bool {
+        self.loop_start.is_some() || self.loop_end.is_some() || self.loop_crossfade.is_some()
+    }
+
+    pub fn mic_path(&self, mic: &str) -> Option<&str> {
+        if mic == MAIN_MIC {
+            Some(&self.path)
+        } else {
+            self.mics.get(mic).map(String::as_str)
+        }
+    }
+}
+
+fn unity() -> f32 {
+    1.0
+}
+
+// A mic position the instrument offers and its level in the default mix.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MicPosition {
+    pub name: String,
+    #[serde(default = "unity")]
+    pub level: f32,
+}
+
+// How a key picks between samples that share a layer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RoundRobin {
+    #[default]
+    Cycle,
+    Random,
+}
+
+impl RoundRobin {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "cycle" => Some(Self::Cycle),
+            "random" => Some(Self::Random),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -14,6 +76,18 @@ pub struct KeyData {
     pub lokey: String,
     pub hikey: String,
     pub samples: Vec<SampleInfo>,
+    // Key-release noises, played when the damper falls back on the strings.
+    #[serde(default)]
+    pub release_samples: Vec<SampleInfo>,
+}
+
+// Sustain pedal mechanism noises, played as the pedal goes down or comes up.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct PedalSamples {
+    #[serde(default)]
+    pub down: Vec<SampleInfo>,
+    #[serde(default)]
+    pub up: Vec<SampleInfo>,
 }
 
 impl KeyData {
@@ -83,6 +83,74 @@ impl Settings {
     pub fn slow_release(&self) -> Option<f32> {
         self.get_f32("slow_release")
     }
+
+    pub fn attack_time(&self) -> Option<f32> {
+        self.get_f32("attack_time")
+    }
+
+    pub fn decay_time(&self) -> Option<f32> {
+        self.get_f32("decay_time")
+    }
+
+    pub fn sustain_level(&self) -> Option<f32> {
+        self.get_f32("sustain_level")
+    }
+
+    pub fn release_time(&self) -> Option<f32> {
+        self.get_f32("release_time")
+    }
+
+    pub fn layer_crossfade(&self) -> Option<bool> {
+        self.get_bool("layer_crossfade")
+    }
+
+    pub fn round_robin(&self) -> Option<&String> {
+        self.get_string("round_robin")
+    }
+
+    pub fn polyphony(&self) -> Option<i32> {
+        self.get_i32("polyphony")
+    }
+
+    pub fn voice_stealing(&self) -> Option<&String> {
+        self.get_string("voice_stealing")
+    }
+
+    pub fn pitch_bend_range(&self) -> Option<f32> {
+        self.get_f32("pitch_bend_range")
+    }
+
+    pub fn mod_wheel(&self) -> Option<&String> {
+        self.get_string("mod_wheel")
+    }
+
+    pub fn key_panning(&self) -> Option<&String> {
+        self.get_string("key_panning")
+    }
+
+    pub fn stereo_width(&self) -> Option<f32> {
+        self.get_f32("stereo_width")
+    }
+
+    pub fn sympathetic_resonance(&self) -> Option<f32> {
+        self.get_f32("sympathetic_resonance")
+    }
+
+    pub fn undamped_from(&self) -> Option<i32> {
+        self.get_i32("undamped_from")
+    }
+
+    pub fn release_volume(&self) -> Option<f32> {
+        self.get_f32("release_volume")
+    }
+
+    pub fn release_decay(&self) -> Option<f32> {
+        self.get_f32("release_decay")
+    }
+
+    pub fn pedal_noise_volume(&self) -> Option<f32> {
+        self.get_f32("pedal_noise_volume")
+    }
 }
 
 impl Default for Settings {
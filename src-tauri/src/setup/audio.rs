@@ -1,185 +1,491 @@
-use crate::engine::{cache, decoder, parser};
+use crate::engine::backend::device::DeviceConfig;
+use crate::engine::backend::{self, AudioBackend, BackendKind, StreamFormat};
+use crate::engine::clock::EngineClock;
+use crate::engine::controller::ModTarget;
+use crate::engine::effects::{MasterBus, MasterParams};
+use crate::engine::interpolate::Interpolation;
+use crate::engine::mixer::{
+    MicTake, Mixer, ReleaseTrigger, ScheduledCommand, StealPolicy, VoiceStats, DEFAULT_POLYPHONY,
+    MAX_MICS,
+};
+use crate::engine::sample::AudioSample;
+use crate::engine::stereo::StereoImage;
+use crate::engine::{cache, decoder, parser, pedal};
 use crate::error::{AudioError, Result};
 use crate::extra::sketch::instrument::release;
+use crate::extra::sketch::instrument::sample::{KeyData, RoundRobin, SampleInfo};
 use crate::setup::config::InstrumentConfig;
 use crate::state;
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use serde::Serialize;
+use std::collections::hash_map::{DefaultHasher, RandomState};
 use std::collections::HashMap;
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::hash::{BuildHasher, BuildHasherDefault};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::{self, Receiver, SyncSender};
 use std::sync::{Arc, Mutex};
-use tauri::Emitter;
+use std::time::Duration;
+use tauri::{Emitter, Manager};
 
-const MAX_VOICES: usize = 64;
+pub use crate::engine::mixer::{AudioCommand, Voice};
 
 const CMD_QUEUE_DEPTH: usize = 512;
 
-#[derive(Debug)]
-pub enum AudioCommand {
-    PlayNote {
-        midi: u8,
-        velocity: u8,
-        data: Arc<Vec<f32>>,
-        pitch_ratio: f32,
-    },
-    StopNote { midi: u8 },
+const RETIRE_QUEUE_DEPTH: usize = 256;
+
+const WATCHDOG_INTERVAL: Duration = Duration::from_millis(500);
+
+const RECOVERY_RETRY: Duration = Duration::from_secs(2);
+
+// One running output: its command queue, backend and master bus control.
+// Restarting the output replaces all three, since the mixer is rebuilt for
+// the new format.
+struct Stream {
+    kind: BackendKind,
+    cmd_tx: SyncSender<ScheduledCommand>,
+    backend: Box<dyn AudioBackend>,
+    master: MasterBus,
+}
+
+// Mixer state owned by the handle rather than the stream, so every new mixer
+// starts from it.
+#[derive(Debug, Clone, Copy)]
+struct MixerSettings {
+    interpolation: Interpolation,
+    volume: f32,
+    polyphony: usize,
+    steal_policy: StealPolicy,
+    mod_target: ModTarget,
+    resonance: f32,
+    undamped_from: Option<u8>,
+    image: StereoImage,
+    mic_levels: [f32; MAX_MICS],
+    tuning: f32,
+}
+
+impl Default for MixerSettings {
+    fn default() -> Self {
+        Self {
+            interpolation: Interpolation::default(),
+            volume: 1.0,
+            polyphony: DEFAULT_POLYPHONY,
+            steal_policy: StealPolicy::default(),
+            mod_target: ModTarget::default(),
+            resonance: 0.0,
+            undamped_from: None,
+            image: StereoImage::default(),
+            mic_levels: [1.0; MAX_MICS],
+            tuning: 1.0,
+        }
+    }
 }
 
-#[derive(Clone)]
-pub struct Voice {
-    pub data: Arc<Vec<f32>>,
-    pub playhead: f32,
-    pub pitch_ratio: f32,
-    pub midi_note: u8,
-    pub is_releasing: bool,
-    pub volume: f32,
+#[derive(Debug, Clone, Serialize)]
+pub struct VoiceReport {
+    pub polyphony: usize,
+    pub steal_policy: StealPolicy,
+    pub active: usize,
+    pub peak: usize,
+    // Voices cut short to make room since the app started.
+    pub stolen: u64,
 }
 
+// `effects` and `mixer` are the last ones applied, so a rebuilt stream comes
+// back the same even if they changed while the output was down.
 pub struct AudioHandle {
-    pub cmd_tx: SyncSender<AudioCommand>,
-    pub is_sustained: Arc<Mutex<bool>>,
-    pub _stream: cpal::Stream,
+    stream: Mutex<Option<Stream>>,
+    effects: Mutex<MasterParams>,
+    mixer: Mutex<MixerSettings>,
+    stats: Arc<VoiceStats>,
+    clock: Arc<EngineClock>,
+    retire_tx: SyncSender<Arc<AudioSample>>,
 }
 
-pub fn start_stream() -> Result<AudioHandle> {
-    let host = cpal::default_host();
-    let device = host
-        .default_output_device()
-        .ok_or(AudioError::NoOutputDevice)?;
-    let config = device.default_output_config()?;
-    let channels = config.channels() as usize;
-
-    let (cmd_tx, cmd_rx): (SyncSender<AudioCommand>, Receiver<AudioCommand>) =
-        mpsc::sync_channel(CMD_QUEUE_DEPTH);
+impl AudioHandle {
+    fn with_stream<T>(&self, f: impl FnOnce(&mut Stream) -> T) -> Result<T> {
+        self.stream
+            .lock()
+            .unwrap()
+            .as_mut()
+            .map(f)
+            .ok_or_else(|| AudioError::StreamError("Audio output is not running".to_string()))
+    }
 
-    let is_sustained = Arc::new(Mutex::new(false));
-    let sustained_clone = Arc::clone(&is_sustained);
-
-    let mut voices: Vec<Voice> = Vec::with_capacity(MAX_VOICES);
-    let mut mix: Vec<f32> = Vec::new();
-
-    let stream = device
-        .build_output_stream(
-            &config.into(),
-            move |output: &mut [f32], _| {
-                output.fill(0.0);
-
-                let sustained = sustained_clone.try_lock().map(|g| *g).unwrap_or(false);
-
-                while let Ok(cmd) = cmd_rx.try_recv() {
-                    match cmd {
-                        AudioCommand::PlayNote {
-                            midi,
-                            velocity,
-                            data,
-                            pitch_ratio,
-                        } => {
-                            for v in voices.iter_mut() {
-                                if v.midi_note == midi && !v.is_releasing {
-                                    v.is_releasing = true;
-                                }
-                            }
-                            if voices.len() >= MAX_VOICES {
-                                if let Some(idx) = voices.iter().position(|v| v.is_releasing) {
-                                    voices.remove(idx);
-                                } else {
-                                    voices.remove(0);
-                                }
-                            }
-                            voices.push(Voice {
-                                data,
-                                playhead: 0.0,
-                                pitch_ratio,
-                                midi_note: midi,
-                                is_releasing: false,
-                                volume: velocity as f32 / 127.0,
-                            });
-                        }
-                        AudioCommand::StopNote { midi } => {
-                            for v in voices.iter_mut() {
-                                if v.midi_note == midi {
-                                    v.is_releasing = true;
-                                }
-                            }
-                        }
-                    }
-                }
+    pub fn send(&self, cmd: AudioCommand) -> Result<()> {
+        self.send_at(0, cmd)
+    }
 
-                let fast = release::get_fast();
-                let slow = release::get_slow();
-                let num_frames = output.len() / channels;
+    // Queues `cmd` for engine frame `frame`; frames already played apply at
+    // the start of the next buffer.
+    pub fn send_at(&self, frame: u64, cmd: AudioCommand) -> Result<()> {
+        let scheduled = ScheduledCommand {
+            frame,
+            command: cmd,
+        };
+        self.with_stream(|s| {
+            s.cmd_tx
+                .try_send(scheduled)
+                .map_err(|e| AudioError::StreamError(format!("Cannot queue audio command: {}", e)))
+        })?
+    }
 
-                if mix.len() < num_frames {
-                    mix.resize(num_frames, 0.0);
-                }
-                for s in mix[..num_frames].iter_mut() {
-                    *s = 0.0;
-                }
+    pub fn clock(&self) -> &EngineClock {
+        &self.clock
+    }
 
-                for v in voices.iter_mut() {
-                    for frame_idx in 0..num_frames {
-                        let pos = v.playhead as usize;
-                        if pos + 1 >= v.data.len() {
-                            v.volume = 0.0;
-                            break;
-                        }
-                        let frac = v.playhead - pos as f32;
-                        let sample = v.data[pos] * (1.0 - frac) + v.data[pos + 1] * frac;
-                        mix[frame_idx] += sample * v.volume;
-
-                        if v.is_releasing {
-                            v.volume *= if sustained { slow } else { fast };
-                        }
-                        v.playhead += v.pitch_ratio;
-                    }
+    pub fn backend_name(&self) -> &'static str {
+        self.with_stream(|s| s.backend.name()).unwrap_or("none")
+    }
+
+    pub fn format(&self) -> Option<StreamFormat> {
+        self.with_stream(|s| s.backend.format()).ok()
+    }
+
+    pub fn apply_effects(&self, params: &MasterParams) {
+        *self.effects.lock().unwrap() = params.clone();
+        let _ = self.with_stream(|s| s.master.apply(params));
+    }
+
+    fn settings(&self) -> MixerSettings {
+        *self.mixer.lock().unwrap()
+    }
+
+    pub fn set_interpolation(&self, mode: Interpolation) {
+        self.mixer.lock().unwrap().interpolation = mode;
+        let _ = self.send(AudioCommand::Interpolation { mode });
+    }
+
+    pub fn set_voice_limit(&self, polyphony: usize, policy: StealPolicy) {
+        {
+            let mut settings = self.mixer.lock().unwrap();
+            settings.polyphony = polyphony;
+            settings.steal_policy = policy;
+        }
+        let _ = self.send(AudioCommand::VoiceLimit { polyphony, policy });
+    }
+
+    pub fn set_mod_target(&self, target: ModTarget) {
+        self.mixer.lock().unwrap().mod_target = target;
+        let _ = self.send(AudioCommand::ModTarget { target });
+    }
+
+    pub fn set_stereo_image(&self, image: StereoImage) {
+        self.mixer.lock().unwrap().image = image;
+        let _ = self.send(AudioCommand::StereoImage { image });
+    }
+
+    pub fn set_mic_levels(&self, levels: [f32; MAX_MICS]) {
+        self.mixer.lock().unwrap().mic_levels = levels;
+        let _ = self.send(AudioCommand::MicLevels { levels });
+    }
+
+    pub fn set_tuning(&self, ratio: f32) {
+        self.mixer.lock().unwrap().tuning = ratio;
+        let _ = self.send(AudioCommand::Tuning { ratio });
+    }
+
+    pub fn set_resonance(&self, amount: f32, undamped_from: Option<u8>) {
+        {
+            let mut settings = self.mixer.lock().unwrap();
+            settings.resonance = amount;
+            settings.undamped_from = undamped_from;
+        }
+        let _ = self.send(AudioCommand::Resonance {
+            amount,
+            undamped_from,
+        });
+    }
+
+    pub fn voice_report(&self) -> VoiceReport {
+        let settings = self.settings();
+        VoiceReport {
+            polyphony: settings.polyphony,
+            steal_policy: settings.steal_policy,
+            active: self.stats.active(),
+            peak: self.stats.peak(),
+            stolen: self.stats.stolen(),
+        }
+    }
+
+    pub fn set_volume(&self, value: f32) {
+        let value = value.clamp(0.0, 1.0);
+        self.mixer.lock().unwrap().volume = value;
+        let _ = self.send(AudioCommand::Volume { value });
+    }
+
+    // Swaps the output at runtime. The old stream is closed first since some
+    // drivers only allow one; if the new one fails the old one is reopened.
+    pub fn restart(&self, kind: &BackendKind, effects: &MasterParams) -> Result<()> {
+        *self.effects.lock().unwrap() = effects.clone();
+        // Settings are read before the stream lock: setters lock them first.
+        let settings = self.settings();
+        let mut current = self.stream.lock().unwrap();
+        let previous = current.take().map(|s| s.kind);
+
+        match open_stream(self, kind, effects, settings) {
+            Ok(stream) => {
+                *current = Some(stream);
+                Ok(())
+            }
+            Err(e) => {
+                if let Some(previous) = previous {
+                    *current = open_stream(self, &previous, effects, settings).ok();
                 }
+                Err(e)
+            }
+        }
+    }
 
-                voices.retain(|v| v.volume > 0.001 && (v.playhead as usize + 1) < v.data.len());
+    // Closes the output if its backend reported a fatal error, returning the
+    // error and the kind it was running so recovery can reopen it.
+    fn take_failed(&self) -> Option<(String, BackendKind)> {
+        let mut current = self.stream.lock().unwrap();
+        let error = current.as_ref()?.backend.failure()?;
+        current.take().map(|s| (error, s.kind))
+    }
 
-                let num_voices = voices.len().max(1) as f32;
-                let gain = (1.0 / num_voices.sqrt()).min(1.0) * 0.8;
+    // Reopens the lost output, falling back to the system default device.
+    // The new stream is opened without holding the stream lock, so commands
+    // fail fast instead of queueing behind retries. Leaves alone a stream
+    // the user opened in the meantime.
+    fn recover(&self, lost: &BackendKind) -> Result<StreamFormat> {
+        if let Some(stream) = self.stream.lock().unwrap().as_ref() {
+            return Ok(stream.backend.format());
+        }
 
-                for frame_idx in 0..num_frames {
-                    let s = (mix[frame_idx] * gain).tanh();
-                    for ch in 0..channels {
-                        let idx = frame_idx * channels + ch;
-                        if idx < output.len() {
-                            output[idx] = s;
-                        }
+        let effects = self.effects.lock().unwrap().clone();
+        let settings = self.settings();
+        let fallback = BackendKind::Device(DeviceConfig::default());
+        let mut result = open_stream(self, lost, &effects, settings);
+        if result.is_err() && *lost != fallback {
+            result = open_stream(self, &fallback, &effects, settings);
+        }
+        let stream = result?;
+
+        let mut current = self.stream.lock().unwrap();
+        if let Some(existing) = current.as_ref() {
+            let format = existing.backend.format();
+            drop(current);
+            drop(stream);
+            return Ok(format);
+        }
+        let format = stream.backend.format();
+        *current = Some(stream);
+        Ok(format)
+    }
+}
+
+// Watches the managed AudioHandle for a dead output (device unplugged, sound
+// server restarted) and rebuilds it, telling the frontend through
+// `audio_stream_lost` and `audio_stream_recovered`.
+pub fn watch_stream(app: tauri::AppHandle) -> Result<()> {
+    std::thread::Builder::new()
+        .name("rakund-audio-watchdog".to_string())
+        .spawn(move || loop {
+            std::thread::sleep(WATCHDOG_INTERVAL);
+
+            let handle = app.state::<AudioHandle>();
+            let Some((error, kind)) = handle.take_failed() else {
+                continue;
+            };
+
+            eprintln!("[AUDIO] Output lost: {}", error);
+            let _ = app.emit("audio_stream_lost", serde_json::json!({ "error": error }));
+
+            let format = loop {
+                match handle.recover(&kind) {
+                    Ok(format) => break format,
+                    Err(e) => {
+                        eprintln!("[AUDIO] Recovery failed ({}), retrying", e);
+                        std::thread::sleep(RECOVERY_RETRY);
                     }
                 }
-            },
-            |err| eprintln!("Audio stream error: {:?}", err),
-            None,
-        )
-        .map_err(AudioError::BuildStreamError)?;
-
-    stream.play().map_err(AudioError::PlayStreamError)?;
-    Ok(AudioHandle {
+            };
+
+            println!("[AUDIO] Output recovered on {}", handle.backend_name());
+            let _ = app.emit(
+                "audio_stream_recovered",
+                serde_json::json!({
+                    "backend":     handle.backend_name(),
+                    "sample_rate": format.sample_rate,
+                    "channels":    format.channels
+                }),
+            );
+        })
+        .map(|_| ())
+        .map_err(|e| AudioError::StreamError(format!("Cannot start audio watchdog: {}", e)))
+}
+
+// Plays on the saved output device, falling back to the system default if
+// that device is gone, at the saved master volume.
+pub fn start_stream() -> Result<AudioHandle> {
+    let mut kind = BackendKind::from_env();
+    if let BackendKind::Device(config) = &mut kind {
+        *config = state::read().map(|s| s.output_device).unwrap_or_default();
+    }
+
+    let handle = match start_stream_with(&kind) {
+        Err(e) if matches!(&kind, BackendKind::Device(c) if *c != DeviceConfig::default()) => {
+            eprintln!("[AUDIO] Saved output unavailable ({}), using default", e);
+            start_stream_with(&BackendKind::Device(DeviceConfig::default()))
+        }
+        result => result,
+    }?;
+
+    if let Ok(state) = state::read() {
+        if let Some(volume) = state.master_volume {
+            handle.set_volume(volume);
+        }
+        handle.set_tuning(state.tuning.ratio());
+    }
+    Ok(handle)
+}
+
+pub fn start_stream_with(kind: &BackendKind) -> Result<AudioHandle> {
+    let (retire_tx, retire_rx) = mpsc::sync_channel::<Arc<AudioSample>>(RETIRE_QUEUE_DEPTH);
+    std::thread::Builder::new()
+        .name("rakund-voice-gc".to_string())
+        .spawn(move || for _ in retire_rx {})
+        .map_err(|e| AudioError::StreamError(format!("Cannot start voice collector: {}", e)))?;
+
+    let handle = AudioHandle {
+        stream: Mutex::new(None),
+        effects: Mutex::new(MasterParams::default()),
+        mixer: Mutex::new(MixerSettings::default()),
+        stats: Arc::new(VoiceStats::default()),
+        clock: Arc::new(EngineClock::default()),
+        retire_tx,
+    };
+    let stream = open_stream(
+        &handle,
+        kind,
+        &MasterParams::default(),
+        MixerSettings::default(),
+    )?;
+    *handle.stream.lock().unwrap() = Some(stream);
+    Ok(handle)
+}
+
+// Builds a mixer from `settings` and starts `kind` on it. Locks neither
+// `handle.stream`, which callers may be holding, nor `handle.mixer`, which
+// setters lock before the stream.
+fn open_stream(
+    handle: &AudioHandle,
+    kind: &BackendKind,
+    effects: &MasterParams,
+    settings: MixerSettings,
+) -> Result<Stream> {
+    let (cmd_tx, cmd_rx): (SyncSender<ScheduledCommand>, Receiver<ScheduledCommand>) =
+        mpsc::sync_channel(CMD_QUEUE_DEPTH);
+
+    let mut master = None;
+    let master_slot = &mut master;
+    let retire_tx = handle.retire_tx.clone();
+    let stats = Arc::clone(&handle.stats);
+    let clock = Arc::clone(&handle.clock);
+
+    let backend = backend::start(kind, move |format| {
+        let mut mixer = Mixer::new(format.sample_rate, format.channels);
+        mixer.set_retire_queue(retire_tx);
+        mixer.set_interpolation(settings.interpolation);
+        mixer.set_volume(settings.volume);
+        mixer.set_voice_limit(settings.polyphony, settings.steal_policy);
+        mixer.set_mod_target(settings.mod_target);
+        mixer.set_resonance(settings.resonance, settings.undamped_from);
+        mixer.set_stereo_image(settings.image);
+        mixer.set_mic_levels(settings.mic_levels);
+        mixer.set_tuning(settings.tuning);
+        mixer.set_stats(stats);
+        mixer.set_clock(clock);
+
+        let (bus, chain) = MasterBus::new(format.sample_rate, effects);
+        mixer.set_effects(Box::new(chain));
+        *master_slot = Some(bus);
+
+        Box::new(move |output: &mut [f32]| {
+            while let Ok(cmd) = cmd_rx.try_recv() {
+                mixer.schedule(cmd);
+            }
+
+            mixer.render(output);
+        })
+    })?;
+
+    let master = master.ok_or_else(|| {
+        AudioError::StreamError("Backend started without a render callback".to_string())
+    })?;
+
+    Ok(Stream {
+        kind: kind.clone(),
         cmd_tx,
-        is_sustained,
-        _stream: stream,
+        backend,
+        master,
     })
 }
 
 pub fn load_instrument(folder: &str) -> Result<InstrumentConfig> {
-    let instrument_dir = state::instruments_dir()?.join(folder);
-    load_instrument_from_path(&instrument_dir, None::<&tauri::AppHandle>)
+    load_instrument_from_path(folder, None::<&tauri::AppHandle>)
 }
 
 pub fn load_instrument_with_progress(
     folder: &str,
     app: &tauri::AppHandle,
 ) -> Result<InstrumentConfig> {
-    let instrument_dir = state::instruments_dir()?.join(folder);
-    load_instrument_from_path(&instrument_dir, Some(app))
+    load_instrument_from_path(folder, Some(app))
+}
+
+// Level of each mic slot: the user's setting for this instrument folder,
+// else the level the instrument declares.
+pub fn mic_levels(config: &InstrumentConfig, folder: &str) -> [f32; MAX_MICS] {
+    let user = state::read()
+        .ok()
+        .and_then(|mut s| s.mic_levels.remove(folder))
+        .unwrap_or_default();
+    let mut levels = [0.0; MAX_MICS];
+    for (level, mic) in levels.iter_mut().zip(config.mics()) {
+        *level = user
+            .get(&mic.name)
+            .copied()
+            .unwrap_or(mic.level)
+            .clamp(0.0, 1.0);
+    }
+    levels
+}
+
+// Applies the loop points `info` sets over any the file carries.
+fn apply_loop(sample: &mut AudioSample, info: &SampleInfo) {
+    if !info.has_loop_settings() {
+        return;
+    }
+    let file_loop = sample.loop_region;
+    let start = info.loop_start.or(file_loop.map(|l| l.start));
+    let end = info
+        .loop_end
+        .map(|last| last + 1)
+        .or(file_loop.map(|l| l.end));
+    let crossfade = info
+        .loop_crossfade
+        .or(file_loop.map(|l| l.crossfade))
+        .unwrap_or(0);
+    if let (Some(start), Some(end)) = (start, end) {
+        sample.set_loop(start, end, crossfade);
+    }
+}
+
+// Where a decoded instrument sample goes in the cache.
+enum CacheSlot {
+    Note(u8, usize, usize),
+    Release(u8, usize),
+    Pedal(bool, usize),
 }
 
+// Mic positions at zero level are skipped, so they take no memory.
 fn load_instrument_from_path(
-    instrument_dir: &Path,
+    folder: &str,
     app: Option<&tauri::AppHandle>,
 ) -> Result<InstrumentConfig> {
+    let instrument_dir = state::instruments_dir()?.join(folder);
     let json_path = instrument_dir.join("instrument.json");
 
     let raw = fs::read_to_string(&json_path)
@@ -189,7 +495,8 @@ fn load_instrument_from_path(
         .map_err(|e| AudioError::InstrumentError(format!("Invalid instrument.json: {}", e)))?;
 
     let fast_release = config.fast_release().unwrap_or(0.9998);
-    let slow_release = config.slow_release().unwrap_or(0.99999);
+    // Without `slow_release`, strings held by the pedal ring as recorded.
+    let slow_release = config.slow_release().unwrap_or(1.0);
     release::set(fast_release, slow_release);
 
     cache::clear();
@@ -201,52 +508,81 @@ fn load_instrument_from_path(
         .collect();
     midi_keys.sort();
 
-    let total = midi_keys
-        .iter()
-        .map(|m| {
-            config
-                .piano_keys
-                .get(&m.to_string())
-                .map(|k| k.samples.len())
-                .unwrap_or(0)
-        })
-        .sum::<usize>();
-
-    let mut done = 0usize;
-    let mut last_emitted_pct = -1i32;
-    let mut file_cache: HashMap<String, Arc<Vec<f32>>> = HashMap::new();
+    let mics = config.mics();
+    let levels = mic_levels(&config, folder);
 
+    let mut jobs: Vec<(CacheSlot, &str, &SampleInfo)> = Vec::new();
     for midi in &midi_keys {
         let key_data = &config.piano_keys[&midi.to_string()];
-        for (sample_idx, sample_info) in key_data.samples.iter().enumerate() {
-            let sample_path = instrument_dir.join(&sample_info.path);
-            let file_key = sample_path.to_string_lossy().to_lowercase();
+        for (idx, info) in key_data.samples.iter().enumerate() {
+            for (slot, mic) in mics.iter().enumerate() {
+                if levels[slot] <= 0.0 {
+                    continue;
+                }
+                if let Some(path) = info.mic_path(&mic.name) {
+                    jobs.push((CacheSlot::Note(*midi, slot, idx), path, info));
+                }
+            }
+        }
+        for (idx, info) in key_data.release_samples.iter().enumerate() {
+            jobs.push((CacheSlot::Release(*midi, idx), &info.path, info));
+        }
+    }
+    for (idx, info) in config.pedal_samples.down.iter().enumerate() {
+        jobs.push((CacheSlot::Pedal(true, idx), &info.path, info));
+    }
+    for (idx, info) in config.pedal_samples.up.iter().enumerate() {
+        jobs.push((CacheSlot::Pedal(false, idx), &info.path, info));
+    }
 
-            let data = if let Some(cached) = file_cache.get(&file_key) {
-                cached.clone()
-            } else {
-                let decoded = decoder::decode(&sample_path.to_string_lossy())?;
-                file_cache.insert(file_key, decoded.clone());
-                decoded
-            };
+    let total = jobs.len();
+    let mut done = 0usize;
+    let mut last_emitted_pct = -1i32;
+    let mut file_cache: HashMap<String, Arc<AudioSample>> = HashMap::new();
+
+    for (slot, path, info) in jobs {
+        let sample_path = instrument_dir.join(path);
+        let mut file_key = sample_path.to_string_lossy().to_lowercase();
+        // Files looped differently by different entries are kept apart.
+        if info.has_loop_settings() {
+            file_key = format!(
+                "{}#{:?}",
+                file_key,
+                (info.loop_start, info.loop_end, info.loop_crossfade)
+            );
+        }
 
-            cache::insert_by_index(*midi, sample_idx, data);
-            done += 1;
-
-            if let Some(handle) = app {
-                let pct = ((done as f32 / total as f32) * 100.0) as i32;
-                if pct != last_emitted_pct {
-                    last_emitted_pct = pct;
-                    let _ = handle.emit(
-                        "load_progress",
-                        serde_json::json!({
-                            "progress": pct as f32,
-                            "loaded":   done,
-                            "total":    total,
-                            "status":   "loading"
-                        }),
-                    );
-                }
+        let data = if let Some(cached) = file_cache.get(&file_key) {
+            cached.clone()
+        } else {
+            let mut decoded = decoder::decode(&sample_path.to_string_lossy())?;
+            if let Some(sample) = Arc::get_mut(&mut decoded) {
+                apply_loop(sample, info);
+            }
+            file_cache.insert(file_key, decoded.clone());
+            decoded
+        };
+
+        match slot {
+            CacheSlot::Note(midi, mic, idx) => cache::insert_mic(midi, mic, idx, data),
+            CacheSlot::Release(midi, idx) => cache::insert_release(midi, idx, data),
+            CacheSlot::Pedal(down, idx) => cache::insert_pedal(down, idx, data),
+        }
+        done += 1;
+
+        if let Some(handle) = app {
+            let pct = ((done as f32 / total as f32) * 100.0) as i32;
+            if pct != last_emitted_pct {
+                last_emitted_pct = pct;
+                let _ = handle.emit(
+                    "load_progress",
+                    serde_json::json!({
+                        "progress": pct as f32,
+                        "loaded":   done,
+                        "total":    total,
+                        "status":   "loading"
+                    }),
+                );
             }
         }
     }
@@ -285,3 +621,278 @@ pub fn pitch_ratio(recorded_midi: u8, target_midi: u8) -> f32 {
 pub fn pitch_to_midi(pitch: &str) -> Option<u8> {
     parser::note_name_to_midi(pitch)
 }
+
+pub fn layer_for_velocity(config: &InstrumentConfig, velocity: u8) -> Option<String> {
+    let mut ranges: Vec<_> = config.general.layers.values().collect();
+    ranges.sort_by_key(|r| r.lovel);
+
+    ranges
+        .iter()
+        .find(|r| velocity >= r.lovel && velocity <= r.hivel)
+        .or_else(|| {
+            ranges.iter().min_by_key(|r| {
+                let mid = (r.lovel as i16 + r.hivel as i16) / 2;
+                (velocity as i16 - mid).abs()
+            })
+        })
+        .map(|r| r.name.to_uppercase())
+}
+
+// Splits a velocity between its own layer and the nearest neighbour: a layer
+// plays alone at the middle of its range and shares the note evenly with the
+// adjacent layer at the range boundary.
+pub fn layer_crossfade(
+    config: &InstrumentConfig,
+    velocity: u8,
+) -> Option<(String, Option<(String, f32)>)> {
+    let mut ranges: Vec<_> = config.general.layers.values().collect();
+    ranges.sort_by_key(|r| r.lovel);
+
+    let own = layer_for_velocity(config, velocity)?;
+    let idx = ranges.iter().position(|r| r.name.to_uppercase() == own)?;
+    let range = ranges[idx];
+
+    let span = (range.hivel as f32 - range.lovel as f32).max(1.0);
+    let offset = ((velocity as f32 - range.lovel as f32) / span).clamp(0.0, 1.0) - 0.5;
+    let neighbour = if offset > 0.0 {
+        ranges.get(idx + 1)
+    } else if offset < 0.0 {
+        idx.checked_sub(1).and_then(|i| ranges.get(i))
+    } else {
+        None
+    };
+
+    let blend = neighbour.map(|n| (n.name.to_uppercase(), offset.abs()));
+    Some((own, blend))
+}
+
+// Where each key's round-robin groups stand, for strikes and release
+// triggers separately so note-offs never take a strike's turn. Live playing
+// shares `LIVE_TURNS`; offline renders start a seeded set of their own, so
+// rendering a session twice picks the same samples.
+pub struct RoundRobinTurns {
+    strikes: [AtomicUsize; 128],
+    releases: [AtomicUsize; 128],
+    // Random mode hashes this with the key and its count; without a seed
+    // every pick is fresh.
+    seed: Option<u64>,
+}
+
+impl RoundRobinTurns {
+    pub const fn new(seed: Option<u64>) -> Self {
+        Self {
+            strikes: [const { AtomicUsize::new(0) }; 128],
+            releases: [const { AtomicUsize::new(0) }; 128],
+            seed,
+        }
+    }
+
+    fn next(&self, counters: &[AtomicUsize; 128], midi: u8, mode: RoundRobin) -> usize {
+        let count = counters[midi as usize % 128].fetch_add(1, Ordering::Relaxed);
+        match (mode, self.seed) {
+            (RoundRobin::Cycle, _) => count,
+            (RoundRobin::Random, Some(seed)) => BuildHasherDefault::<DefaultHasher>::default()
+                .hash_one((seed, midi, count))
+                as usize,
+            (RoundRobin::Random, None) => RandomState::new().hash_one(midi) as usize,
+        }
+    }
+
+    fn next_strike(&self, midi: u8, mode: RoundRobin) -> usize {
+        self.next(&self.strikes, midi, mode)
+    }
+
+    fn next_release(&self, midi: u8, mode: RoundRobin) -> usize {
+        self.next(&self.releases, midi, mode)
+    }
+}
+
+pub static LIVE_TURNS: RoundRobinTurns = RoundRobinTurns::new(None);
+
+// Samples sharing a layer name on one key form a round-robin group; `turn`
+// picks one of them. Una corda samples are only eligible while the soft
+// pedal selects them.
+fn pick_sample(key_data: &KeyData, layer: &str, una_corda: bool, turn: usize) -> Option<usize> {
+    let group: Vec<usize> = key_data
+        .samples
+        .iter()
+        .enumerate()
+        .filter(|(_, s)| s.una_corda == una_corda && s.layer.to_uppercase() == layer)
+        .map(|(idx, _)| idx)
+        .collect();
+
+    if group.is_empty() {
+        return None;
+    }
+    Some(group[turn % group.len()])
+}
+
+// Resolves a note against the loaded instrument and cache. Without an explicit
+// layer the velocity picks one from the instrument's layer ranges, optionally
+// crossfaded with its neighbour. `soft` is the una corda pedal travel: it
+// pushes selection towards softer layers and, once the pedal is mostly down,
+// switches to the key's una corda samples if it has any.
+pub fn note_command(
+    config: &InstrumentConfig,
+    midi: u8,
+    velocity: u8,
+    layer: Option<&str>,
+    soft: f32,
+    turns: &RoundRobinTurns,
+) -> Result<AudioCommand> {
+    let key_data = config
+        .piano_keys
+        .get(&midi.to_string())
+        .ok_or(AudioError::NoteNotFound(midi))?;
+
+    let (layer_upper, blend_layer) = match layer {
+        Some(name) => (Some(name.to_uppercase()), None),
+        None => {
+            let velocity = pedal::soft_velocity(velocity, soft);
+            if config.crossfade_layers() {
+                match layer_crossfade(config, velocity) {
+                    Some((own, blend)) => (Some(own), blend),
+                    None => (None, None),
+                }
+            } else {
+                (layer_for_velocity(config, velocity), None)
+            }
+        }
+    };
+
+    let una_corda = soft >= 0.5 && key_data.samples.iter().any(|s| s.una_corda);
+    let turn = turns.next_strike(midi, config.round_robin());
+
+    let sample_idx = layer_upper
+        .and_then(|upper| pick_sample(key_data, &upper, una_corda, turn))
+        .or_else(|| {
+            key_data
+                .samples
+                .iter()
+                .position(|s| s.una_corda == una_corda)
+        })
+        .unwrap_or(0);
+
+    let blend_idx = blend_layer
+        .and_then(|(name, mix)| pick_sample(key_data, &name, una_corda, turn).map(|i| (i, mix)));
+
+    // Every loaded mic position contributes; the first one carries the note.
+    let mut takes = (0..MAX_MICS).filter_map(|slot| {
+        cache::get_mic(midi, slot, sample_idx).map(|sample| MicTake {
+            slot,
+            sample,
+            blend: blend_idx.and_then(|(idx, _)| cache::get_mic(midi, slot, idx)),
+        })
+    });
+    let main = takes.next().ok_or_else(|| {
+        AudioError::CacheError(format!(
+            "Sample not cached: midi={} idx={}",
+            midi, sample_idx
+        ))
+    })?;
+    let mut mics: [Option<MicTake>; MAX_MICS - 1] = Default::default();
+    for (entry, take) in mics.iter_mut().zip(takes) {
+        *entry = Some(take);
+    }
+
+    let blend = blend_idx.and_then(|(_, mix)| main.blend.map(|sample| (sample, mix)));
+
+    let recorded_midi = pitch_to_midi(&key_data.pitch).unwrap_or(key_data.midi_num());
+
+    Ok(AudioCommand::PlayNote {
+        midi,
+        velocity,
+        sample: main.sample,
+        pitch_ratio: pitch_ratio(recorded_midi, midi),
+        envelope: config.envelope(),
+        blend,
+        mic: main.slot,
+        mics,
+    })
+}
+
+fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+// The key's release-trigger sample, round-robin like its strikes.
+pub fn release_trigger(
+    config: &InstrumentConfig,
+    midi: u8,
+    turns: &RoundRobinTurns,
+) -> Option<ReleaseTrigger> {
+    let key_data = config.piano_keys.get(&midi.to_string())?;
+    if key_data.release_samples.is_empty() {
+        return None;
+    }
+    let idx = turns.next_release(midi, config.round_robin()) % key_data.release_samples.len();
+    let sample = cache::get_release(midi, idx)?;
+    let recorded_midi = pitch_to_midi(&key_data.pitch).unwrap_or(key_data.midi_num());
+
+    Some(ReleaseTrigger {
+        sample,
+        pitch_ratio: pitch_ratio(recorded_midi, midi),
+        gain: db_to_gain(config.release_volume()),
+        decay_db_per_sec: config.release_decay(),
+    })
+}
+
+static PEDAL_TURN: AtomicUsize = AtomicUsize::new(0);
+
+// Mechanism noise for the sustain pedal going down or coming up.
+pub fn pedal_noise(config: &InstrumentConfig, down: bool) -> Option<AudioCommand> {
+    let samples = if down {
+        &config.pedal_samples.down
+    } else {
+        &config.pedal_samples.up
+    };
+    if samples.is_empty() {
+        return None;
+    }
+    let idx = PEDAL_TURN.fetch_add(1, Ordering::Relaxed) % samples.len();
+    let sample = cache::get_pedal(down, idx)?;
+
+    Some(AudioCommand::OneShot {
+        sample,
+        volume: db_to_gain(config.pedal_noise_volume()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FailedBackend;
+
+    impl AudioBackend for FailedBackend {
+        fn name(&self) -> &'static str {
+            "failed"
+        }
+
+        fn format(&self) -> StreamFormat {
+            StreamFormat::default()
+        }
+
+        fn failure(&self) -> Option<String> {
+            Some("device unplugged".to_string())
+        }
+    }
+
+    #[test]
+    fn test_recover_reopens_failed_output() {
+        let handle = start_stream_with(&BackendKind::Null).unwrap();
+        handle
+            .with_stream(|s| s.backend = Box::new(FailedBackend))
+            .unwrap();
+
+        let (error, kind) = handle.take_failed().unwrap();
+        assert_eq!(error, "device unplugged");
+        assert_eq!(kind, BackendKind::Null);
+        assert_eq!(handle.backend_name(), "none");
+
+        let format = handle.recover(&kind).unwrap();
+        assert_eq!(format, StreamFormat::default());
+        assert_eq!(handle.backend_name(), "null");
+        assert!(handle.send(AudioCommand::Sustain { value: 0 }).is_ok());
+    }
+}
@@ -2,9 +2,21 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::default::Default;
 
+use crate::engine::backend::device::DeviceConfig;
+use crate::engine::controller::{ModTarget, DEFAULT_BEND_RANGE};
+use crate::engine::effects::MasterParams;
+use crate::engine::envelope::EnvelopeParams;
+use crate::engine::interpolate::Interpolation;
+use crate::engine::mixer::{StealPolicy, DEFAULT_POLYPHONY, MAX_MICS, MAX_POLYPHONY};
+use crate::engine::stereo::{KeyPanning, StereoImage, MAX_WIDTH};
+use crate::engine::tuning::Tuning;
+use crate::engine::velocity::VelocityCurve;
 use crate::extra::sketch::instrument::settings::Settings;
 use crate::extra::sketch::instrument::{
-    contribution::Contribution, general::General, layer::LayerRangeInfo, sample::KeyData,
+    contribution::Contribution,
+    general::General,
+    layer::LayerRangeInfo,
+    sample::{KeyData, MicPosition, PedalSamples, RoundRobin, MAIN_MIC},
 };
 
 pub fn deserialize_piano_keys<'de, D>(
@@ -38,6 +50,10 @@ pub struct InstrumentConfig {
     pub settings: Settings,
     #[serde(deserialize_with = "deserialize_piano_keys")]
     pub piano_keys: HashMap<String, KeyData>,
+    #[serde(default)]
+    pub pedal_samples: PedalSamples,
+    #[serde(default)]
+    pub mic_positions: Vec<MicPosition>,
 }
 
 impl InstrumentConfig {
@@ -60,6 +76,112 @@ impl InstrumentConfig {
     pub fn slow_release(&self) -> Option<f32> {
         self.settings.slow_release()
     }
+    pub fn envelope(&self) -> EnvelopeParams {
+        EnvelopeParams::from_settings(&self.settings)
+    }
+    pub fn velocity_curve(&self) -> VelocityCurve {
+        VelocityCurve::from_settings(&self.settings)
+    }
+    pub fn interpolation(&self) -> Interpolation {
+        Interpolation::from_settings(&self.settings)
+    }
+    pub fn master_effects(&self) -> MasterParams {
+        MasterParams::from_settings(&self.settings)
+    }
+    pub fn crossfade_layers(&self) -> bool {
+        self.settings.layer_crossfade().unwrap_or(false)
+    }
+    pub fn round_robin(&self) -> RoundRobin {
+        self.settings
+            .round_robin()
+            .and_then(|name| RoundRobin::from_name(name))
+            .unwrap_or_default()
+    }
+    pub fn polyphony(&self) -> usize {
+        self.settings
+            .polyphony()
+            .map(|n| (n.max(1) as usize).min(MAX_POLYPHONY))
+            .unwrap_or(DEFAULT_POLYPHONY)
+    }
+    pub fn steal_policy(&self) -> StealPolicy {
+        self.settings
+            .voice_stealing()
+            .and_then(|name| StealPolicy::from_name(name))
+            .unwrap_or_default()
+    }
+    pub fn pitch_bend_range(&self) -> f32 {
+        self.settings
+            .pitch_bend_range()
+            .map(|r| r.clamp(0.0, 48.0))
+            .unwrap_or(DEFAULT_BEND_RANGE)
+    }
+    pub fn mod_target(&self) -> ModTarget {
+        self.settings
+            .mod_wheel()
+            .and_then(|name| ModTarget::from_name(name))
+            .unwrap_or_default()
+    }
+    // Mic positions by mixer slot: the main mic first, at full level unless
+    // declared otherwise, then the others in the order declared.
+    pub fn mics(&self) -> Vec<MicPosition> {
+        let main = self
+            .mic_positions
+            .iter()
+            .find(|m| m.name == MAIN_MIC)
+            .cloned()
+            .unwrap_or(MicPosition {
+                name: MAIN_MIC.to_string(),
+                level: 1.0,
+            });
+        std::iter::once(main)
+            .chain(
+                self.mic_positions
+                    .iter()
+                    .filter(|m| m.name != MAIN_MIC)
+                    .cloned(),
+            )
+            .take(MAX_MICS)
+            .collect()
+    }
+    pub fn stereo_image(&self) -> StereoImage {
+        StereoImage {
+            panning: self
+                .settings
+                .key_panning()
+                .and_then(|name| KeyPanning::from_name(name))
+                .unwrap_or_default(),
+            width: self
+                .settings
+                .stereo_width()
+                .map(|w| w.clamp(0.0, MAX_WIDTH))
+                .unwrap_or(1.0),
+        }
+    }
+    // Sympathetic resonance level, 0 (off) to 1.
+    pub fn resonance(&self) -> f32 {
+        self.settings
+            .sympathetic_resonance()
+            .map(|r| r.clamp(0.0, 1.0))
+            .unwrap_or(0.0)
+    }
+    // First key without a damper; a real piano's stop around F6 (89).
+    pub fn undamped_from(&self) -> Option<u8> {
+        self.settings
+            .undamped_from()
+            .and_then(|key| u8::try_from(key).ok())
+            .filter(|key| *key < 128)
+    }
+    // Release-trigger level in dB, and how many dB it loses per second the
+    // note was held before release.
+    pub fn release_volume(&self) -> f32 {
+        self.settings.release_volume().unwrap_or(0.0)
+    }
+    pub fn release_decay(&self) -> f32 {
+        self.settings.release_decay().unwrap_or(0.0).max(0.0)
+    }
+    pub fn pedal_noise_volume(&self) -> f32 {
+        self.settings.pedal_noise_volume().unwrap_or(0.0)
+    }
     pub fn get_setting(&self, key: &str) -> Option<&String> {
         self.settings.get_string(key)
     }
@@ -129,6 +251,8 @@ impl InstrumentConfig {
             },
             settings,
             piano_keys: old_config.piano_keys,
+            pedal_samples: PedalSamples::default(),
+            mic_positions: Vec::new(),
         })
     }
 }
@@ -136,4 +260,28 @@ impl InstrumentConfig {
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct AppState {
     pub last_instrument: Option<String>,
+    #[serde(default)]
+    pub velocity_curve: Option<VelocityCurve>,
+    #[serde(default)]
+    pub interpolation: Option<Interpolation>,
+    #[serde(default)]
+    pub master_volume: Option<f32>,
+    #[serde(default)]
+    pub pitch_bend_range: Option<f32>,
+    #[serde(default)]
+    pub key_panning: Option<KeyPanning>,
+    #[serde(default)]
+    pub stereo_width: Option<f32>,
+    #[serde(default)]
+    pub tuning: Tuning,
+    // Mic levels the user set, per instrument folder and position name.
+    #[serde(default)]
+    pub mic_levels: HashMap<String, HashMap<String, f32>>,
+    // Master effects the user saved per instrument folder, and named presets.
+    #[serde(default)]
+    pub effects: HashMap<String, MasterParams>,
+    #[serde(default)]
+    pub effect_presets: HashMap<String, MasterParams>,
+    #[serde(default)]
+    pub output_device: DeviceConfig,
 }
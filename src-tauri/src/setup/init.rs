@@ -1,4 +1,5 @@
 use crate::core;
+use crate::engine::backend::BackendKind;
 use crate::error::AudioError;
 use crate::setup::audio;
 use crate::setup::config::AppState;
@@ -8,7 +9,14 @@ use std::sync::Arc;
 use tokio::sync::RwLock;
 
 pub fn run() -> Result<(), AudioError> {
-    let audio_handle = audio::start_stream()?;
+    let audio_handle = match audio::start_stream() {
+        Ok(handle) => handle,
+        Err(e) => {
+            eprintln!("[INIT] Audio output unavailable ({}), running headless", e);
+            audio::start_stream_with(&BackendKind::Null)?
+        }
+    };
+    println!("[INIT] Audio backend: {}", audio_handle.backend_name());
 
     match state::instruments_dir() {
         Ok(dir) => println!("[INIT] Instruments directory: {:?}", dir),
@@ -28,25 +36,64 @@ pub fn run() -> Result<(), AudioError> {
         .invoke_handler(tauri::generate_handler![
             core::player::play_midi_note,
             core::player::stop_midi_note,
+            core::player::set_sustain,
+            core::player::set_sostenuto,
+            core::player::set_soft_pedal,
+            core::player::set_master_volume,
+            core::player::set_pitch_bend,
+            core::player::set_pitch_bend_range,
+            core::player::set_mod_wheel,
+            core::player::get_voice_stats,
+            core::player::play_notes_batch,
+            core::player::cancel_scheduled_notes,
+            core::player::get_engine_clock,
             core::player::play_note_auto, 
             core::player::load_instrument,
             core::player::get_available_instruments,
             core::player::get_available_instruments_files,
             core::player::get_instrument_info,
             core::player::get_app_state,
+            core::player::set_velocity_curve,
+            core::player::set_interpolation,
+            core::player::get_interpolation_costs,
+            core::player::set_key_panning,
+            core::player::set_stereo_width,
+            core::player::get_mic_levels,
+            core::player::set_mic_level,
+            core::player::get_tuning,
+            core::player::set_transpose,
+            core::player::set_fine_tune,
+            core::player::set_reference_pitch,
             core::player::clear_last_instrument,
             core::visualizer::scan_songs,
             core::visualizer::scan_song_files,
             core::visualizer::load_midi_session,
             core::visualizer::get_session_notes,
             core::visualizer::clear_session,
+            core::renderer::render_session,
+            core::effects::get_master_effects,
+            core::effects::set_master_effects,
+            core::effects::list_effect_presets,
+            core::effects::save_effect_preset,
+            core::effects::load_effect_preset,
+            core::effects::delete_effect_preset,
+            core::effects::list_impulse_responses,
+            core::output::list_audio_hosts,
+            core::output::list_output_devices,
+            core::output::get_output_device,
+            core::output::set_output_device,
             core::manager::create_instrument,
             core::manager::delete_instrument,
             core::manager::create_song,
             core::manager::delete_song,
             core::manager::get_file_metadata,
         ])
-        .setup(|_app| Ok(()))
+        .setup(|app| {
+            if let Err(e) = audio::watch_stream(app.handle().clone()) {
+                eprintln!("[INIT] {}", e);
+            }
+            Ok(())
+        })
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 
@@ -1,3 +1,9 @@
+use crate::engine::backend::device::DeviceConfig;
+use crate::engine::effects::MasterParams;
+use crate::engine::interpolate::Interpolation;
+use crate::engine::stereo::KeyPanning;
+use crate::engine::tuning::Tuning;
+use crate::engine::velocity::VelocityCurve;
 use crate::error::{AudioError, Result};
 use crate::setup::config::AppState;
 use std::fs;
@@ -12,6 +18,24 @@ pub fn songs_dir() -> Result<PathBuf> {
     Ok(dir)
 }
 
+pub fn renders_dir() -> Result<PathBuf> {
+    let base = dirs_next::config_dir()
+        .ok_or_else(|| AudioError::InstrumentError("Cannot find config directory".to_string()))?;
+    let dir = base.join("rakund").join("renders");
+    fs::create_dir_all(&dir)
+        .map_err(|e| AudioError::InstrumentError(format!("Cannot create renders dir: {}", e)))?;
+    Ok(dir)
+}
+
+pub fn irs_dir() -> Result<PathBuf> {
+    let base = dirs_next::config_dir()
+        .ok_or_else(|| AudioError::InstrumentError("Cannot find config directory".to_string()))?;
+    let dir = base.join("rakund").join("irs");
+    fs::create_dir_all(&dir)
+        .map_err(|e| AudioError::InstrumentError(format!("Cannot create irs dir: {}", e)))?;
+    Ok(dir)
+}
+
 fn state_path() -> Result<PathBuf> {
     let base = dirs_next::data_dir()
         .ok_or_else(|| AudioError::InstrumentError("Cannot find data directory".to_string()))?;
@@ -62,3 +86,79 @@ pub fn clear_last_instrument() -> Result<()> {
     state.last_instrument = None;
     write(&state)
 }
+
+pub fn set_velocity_curve(curve: Option<VelocityCurve>) -> Result<()> {
+    let mut state = read()?;
+    state.velocity_curve = curve;
+    write(&state)
+}
+
+pub fn set_master_volume(volume: f32) -> Result<()> {
+    let mut state = read()?;
+    state.master_volume = Some(volume);
+    write(&state)
+}
+
+pub fn set_pitch_bend_range(semitones: Option<f32>) -> Result<()> {
+    let mut state = read()?;
+    state.pitch_bend_range = semitones;
+    write(&state)
+}
+
+pub fn set_key_panning(panning: Option<KeyPanning>) -> Result<()> {
+    let mut state = read()?;
+    state.key_panning = panning;
+    write(&state)
+}
+
+pub fn set_stereo_width(width: Option<f32>) -> Result<()> {
+    let mut state = read()?;
+    state.stereo_width = width;
+    write(&state)
+}
+
+pub fn set_mic_level(folder: &str, mic: &str, level: f32) -> Result<()> {
+    let mut state = read()?;
+    state
+        .mic_levels
+        .entry(folder.to_string())
+        .or_default()
+        .insert(mic.to_string(), level);
+    write(&state)
+}
+
+pub fn set_tuning(tuning: Tuning) -> Result<()> {
+    let mut state = read()?;
+    state.tuning = tuning;
+    write(&state)
+}
+
+pub fn set_interpolation(mode: Option<Interpolation>) -> Result<()> {
+    let mut state = read()?;
+    state.interpolation = mode;
+    write(&state)
+}
+
+pub fn set_instrument_effects(folder: &str, params: MasterParams) -> Result<()> {
+    let mut state = read()?;
+    state.effects.insert(folder.to_string(), params);
+    write(&state)
+}
+
+pub fn save_effect_preset(name: &str, params: MasterParams) -> Result<()> {
+    let mut state = read()?;
+    state.effect_presets.insert(name.to_string(), params);
+    write(&state)
+}
+
+pub fn delete_effect_preset(name: &str) -> Result<()> {
+    let mut state = read()?;
+    state.effect_presets.remove(name);
+    write(&state)
+}
+
+pub fn set_output_device(config: &DeviceConfig) -> Result<()> {
+    let mut state = read()?;
+    state.output_device = config.clone();
+    write(&state)
+}
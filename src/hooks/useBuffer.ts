@@ -24,6 +24,11 @@ export interface MidiSessionInfo {
   file_path: string;
 }
 
+export interface EngineTime {
+  frame: number;
+  sample_rate: number;
+}
+
 export type SessionMode = "perform" | "instruct" | null;
 export type SessionStatus = "idle" | "loading" | "ready" | "playing" | "paused" | "finished";
 
@@ -39,6 +44,10 @@ const SCHEDULER_TICK_MS = 500;
 // Ngưỡng trễ tối đa (ms) được phép tha thứ nếu thread UI bị nghẽn
 const LATE_TOLERANCE_MS = 50;
 
+// Gửi batch audio sớm chừng này, kèm frame đích trên engine clock; engine giữ
+// lại và phát từng voice đúng frame đó.
+const ENGINE_LEAD_MS = 150;
+
 // ── Hook ──────────────────────────────────────────────────────────────────────
 
 export function useBuffer(
@@ -62,6 +71,10 @@ export function useBuffer(
   let schedulerHandle: ReturnType<typeof setInterval> | null = null;
   let scheduledUpToMs = 0;
 
+  // Frame của engine clock và performance.now() tương ứng, cập nhật mỗi tick
+  // của scheduler để hai đồng hồ không bị lệch nhau.
+  let engineSync: { frame: number; sampleRate: number; perfMs: number } | null = null;
+
   // Quản lý bộ nhớ cho timeout: Tự động dọn rác
   const activeTimeouts = new Set<number>();
 
@@ -79,6 +92,26 @@ export function useBuffer(
     activeTimeouts.clear();
   };
 
+  // ── Engine clock ──────────────────────────────────────────────────────────
+
+  const syncEngineClock = () => {
+    const sentMs = performance.now();
+    invoke<EngineTime>("get_engine_clock")
+      .then(t => {
+        if (t.sample_rate <= 0) return;
+        const perfMs = (sentMs + performance.now()) / 2;
+        engineSync = { frame: t.frame, sampleRate: t.sample_rate, perfMs };
+      })
+      .catch(e => console.error("[CLOCK] sync error:", e));
+  };
+
+  // Frame engine sẽ phát tại `perfMs`, hoặc null (phát ngay khi nhận) khi chưa đồng bộ.
+  const engineFrameAt = (perfMs: number): number | null => {
+    if (!engineSync) return null;
+    const { frame, sampleRate, perfMs: syncedAt } = engineSync;
+    return Math.max(0, Math.round(frame + ((perfMs - syncedAt) * sampleRate) / 1000));
+  };
+
   // ── Helpers ───────────────────────────────────────────────────────────────
 
   const isReady = () =>
@@ -154,17 +187,23 @@ export function useBuffer(
       // Nếu batch đã trễ quá ngưỡng cho phép do nghẽn luồng, bỏ qua để tránh dội âm
       if (noteOnDelay < -LATE_TOLERANCE_MS) return;
 
+      const at = engineFrameAt(performance.now() + noteOnDelay);
+
+      // 1. Gửi lệnh AUDIO xuống Rust (1 lần duy nhất cho toàn bộ hợp âm),
+      //    sớm hơn ENGINE_LEAD_MS khi đã có frame đích trên engine clock
       scheduleTask(() => {
-        // 1. Gửi lệnh AUDIO xuống Rust (1 lần duy nhất cho toàn bộ hợp âm)
         invoke("play_notes_batch", {
           notes: currentBatch.map(n => ({
             midi_num: n.midi,
             velocity: n.velocity,
             layer: layer // Lưu ý: Cậu cần đảm bảo backend xử lý layer này hợp lệ
           })),
+          at,
         }).catch(e => console.error("[BATCH] IPC error:", e));
+      }, at === null ? noteOnDelay : noteOnDelay - ENGINE_LEAD_MS);
 
-        // 2. Gửi lệnh VISUAL để cập nhật UI
+      // 2. Gửi lệnh VISUAL để cập nhật UI
+      scheduleTask(() => {
         for (const n of currentBatch) {
           onNoteOn?.(n.midi, n.velocity);
         }
@@ -201,9 +240,11 @@ export function useBuffer(
     const layer = "default"; // CẢNH BÁO: Đừng để chuỗi rỗng. Phải đồng bộ với backend.
 
     scheduledUpToMs = fromMs;
+    syncEngineClock();
     scheduleWindow(sorted, fromMs, layer);
 
     schedulerHandle = setInterval(() => {
+      syncEngineClock();
       const animNow = performance.now() - sessionStartMs;
       if (animNow > scheduledUpToMs) scheduledUpToMs = animNow;
       scheduleWindow(sorted, animNow, layer);
@@ -216,6 +257,8 @@ export function useBuffer(
       schedulerHandle = null;
     }
     clearAllTasks();
+    // Batch đã gửi trước ENGINE_LEAD_MS vẫn nằm chờ trong engine, phải huỷ luôn
+    invoke("cancel_scheduled_notes").catch(e => console.error("[BATCH] cancel error:", e));
     scheduledUpToMs = 0;
   };
 